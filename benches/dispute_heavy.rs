@@ -0,0 +1,38 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ledger::{config::BankConfig, Bank, Ledger};
+
+/// Get a ledger of `rows` transactions: deposits, all of which are then disputed.
+fn dispute_heavy(rows: usize) -> String {
+    let deposits = rows / 2;
+    let mut ledger = String::from("type,client,tx,amount\n");
+    for tx in 0..deposits {
+        ledger.push_str(&format!("deposit,{},{},1\n", tx % 100, tx));
+    }
+    for tx in 0..deposits {
+        ledger.push_str(&format!("dispute,{},{},\n", tx % 100, tx));
+    }
+    ledger
+}
+
+/// Process dispute heavy ledgers of growing sizes: with the disputed transactions looked up in the
+/// in-memory index, rather than by rescanning the ledger, the time no longer grows quadratically
+/// with the rows.
+fn process(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispute_heavy");
+    group.sample_size(10);
+    for rows in [10_000, 50_000, 100_000] {
+        let transactions = dispute_heavy(rows);
+        group.throughput(Throughput::Elements(rows as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(rows), &transactions, |b, t| {
+            b.iter(|| {
+                let ledger = Ledger::from_str(t, Default::default()).unwrap();
+                let mut bank = Bank::new(ledger, BankConfig::default());
+                bank.process().unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, process);
+criterion_main!(benches);
@@ -1,40 +1,110 @@
 use crate::{
     client::ClientId,
+    config::{LogFields, Rounding},
     csv::{account::AccountLog, transaction::TransactionId},
-    transaction::DisputeSate,
+    transaction::{DisputeOutcome, DisputeSate},
 };
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-#[derive(Debug, Clone, Default)]
-pub(crate) struct Account {
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Account {
     /// Client identifier.
     client_id: ClientId,
     /// The total funds that are available for trading, staking, withdrawal, etc.
     /// This should be equal to the total - held amounts.
     available_funds: rust_decimal::Decimal,
-    /// The total funds that are held for dispute.
-    /// This should be equal to total - available amounts.
-    held_funds: HashMap<TransactionId, rust_decimal::Decimal>,
+    /// The funds that are held for dispute, per disputed transaction.
+    /// This is the source of truth for the held funds, and its sum should be equal to
+    /// total - available amounts. It's ordered by transaction so that, beyond the decimal
+    /// precision, its sum is rounded the same way no matter the order the funds were held in.
+    held_funds: BTreeMap<TransactionId, rust_decimal::Decimal>,
     completed_disputes: HashMap<TransactionId, DisputeSate>,
+    /// Transactions whose dispute has been resolved.
+    resolved_disputes: HashSet<TransactionId>,
+    /// The sum of the `held_funds`, recomputed as funds are held or released.
     held_funds_cache: rust_decimal::Decimal,
+    /// The highest total funds the account has reached after any transaction.
+    #[serde(default)]
+    peak_total: rust_decimal::Decimal,
+    /// How far below zero the available funds may be withdrawn.
+    #[serde(default)]
+    overdraft_limit: rust_decimal::Decimal,
     /// Whether the account is locked. An account is locked if a charge back occur.
     locked: bool,
+    /// Whether the account is frozen by an administrative hold, regardless of any chargeback.
+    #[serde(default)]
+    frozen: bool,
+    /// When the funds are rounded.
+    rounding: Rounding,
+}
+
+impl std::fmt::Debug for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_fields(f, LogFields::Full)
+    }
+}
+
+impl Account {
+    /// Format the account with only the given `LogFields`.
+    pub(crate) fn fmt_fields(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        log_fields: LogFields,
+    ) -> std::fmt::Result {
+        match log_fields {
+            LogFields::Minimal => f
+                .debug_struct("Account")
+                .field("client_id", &self.client_id)
+                .field("available_funds", &self.available_funds)
+                .field("held_funds", &self.held_funds_cache)
+                .finish_non_exhaustive(),
+            LogFields::Full => f
+                .debug_struct("Account")
+                .field("client_id", &self.client_id)
+                .field("available_funds", &self.available_funds)
+                .field("held_funds", &self.held_funds)
+                .field("completed_disputes", &self.completed_disputes)
+                .field("resolved_disputes", &self.resolved_disputes)
+                .field("held_funds_cache", &self.held_funds_cache)
+                .field("peak_total", &self.peak_total)
+                .field("overdraft_limit", &self.overdraft_limit)
+                .field("locked", &self.locked)
+                .field("frozen", &self.frozen)
+                .field("rounding", &self.rounding)
+                .finish(),
+        }
+    }
 }
 
 // Assumed from the provided doc that there's only one account per client
-pub(crate) type AccountId = crate::client::ClientId;
+pub type AccountId = crate::client::ClientId;
 
 impl Account {
-    pub(crate) fn new(account_id: AccountId) -> Self {
+    pub(crate) fn new(account_id: AccountId, rounding: Rounding) -> Self {
         Self {
             client_id: account_id,
             available_funds: rust_decimal::Decimal::new(0, 0),
             held_funds: Default::default(),
             completed_disputes: Default::default(),
+            resolved_disputes: Default::default(),
             held_funds_cache: rust_decimal::Decimal::new(0, 0),
+            peak_total: rust_decimal::Decimal::new(0, 0),
+            overdraft_limit: rust_decimal::Decimal::new(0, 0),
             locked: false,
+            frozen: false,
+            rounding,
         }
     }
+    /// Allow withdrawals to take the available funds as low as `-overdraft_limit`.
+    pub(crate) fn with_overdraft_limit(mut self, overdraft_limit: rust_decimal::Decimal) -> Self {
+        self.overdraft_limit = overdraft_limit;
+        self
+    }
+    /// Round the amount as part of an account operation, as per the configured `Rounding`.
+    fn round(&self, amount: rust_decimal::Decimal) -> rust_decimal::Decimal {
+        self.rounding.operation(amount)
+    }
     #[allow(dead_code)]
     pub(crate) fn log_info(&self) {
         tracing::info!(client=%self.client_id(), available=?self.available_funds(), held=?self.held_funds(), total=?self.total_funds(), locked=self.locked());
@@ -42,26 +112,116 @@ impl Account {
     pub(crate) fn to_csv(&self) -> AccountLog {
         AccountLog::from(self)
     }
+    /// Like `to_csv` but also records the peak total funds.
+    pub(crate) fn to_csv_with_peak(&self) -> AccountLog {
+        AccountLog::from(self).with_peak_total(self.output(self.peak_total))
+    }
+    /// Round the amount for the output, as per the configured `Rounding`.
+    fn output(&self, amount: rust_decimal::Decimal) -> rust_decimal::Decimal {
+        match self.rounding {
+            Rounding::Never => amount,
+            Rounding::PerOperation | Rounding::OutputOnly => amount.round_dp(4).normalize(),
+        }
+    }
+    /// Raise the peak total funds if the current total exceeds it.
+    /// Only sampled once a transaction is fully applied, as its intermediate steps may not add up.
+    pub(crate) fn track_peak(&mut self) {
+        if let Some(total) = self.available_funds.checked_add(self.held_funds_cache) {
+            self.peak_total = self.peak_total.max(total);
+        }
+    }
+    /// Get all the disputes which are either ongoing or completed.
+    pub(crate) fn disputes(&self) -> impl Iterator<Item = (TransactionId, DisputeSate)> + '_ {
+        self.held_funds
+            .iter()
+            .map(|(tx, amount)| (*tx, DisputeSate::Disputed(*amount)))
+            .chain(
+                self.completed_disputes
+                    .iter()
+                    .map(|(tx, state)| (*tx, state.clone())),
+            )
+    }
+    /// Check the account for inconsistent funds, recomputing them rather than trusting the cache.
+    pub(crate) fn anomalies(&self) -> Vec<AccountAnomaly> {
+        let mut anomalies = vec![];
+        let held = self.held_funds.values().sum::<rust_decimal::Decimal>();
+        if held != self.held_funds_cache {
+            anomalies.push(AccountAnomaly::HeldMismatch {
+                client_id: self.client_id,
+                cached: self.held_funds_cache,
+                held,
+            });
+        }
+        let recomputed = self.available_funds + held;
+        if self.total_funds() != self.round(recomputed) {
+            anomalies.push(AccountAnomaly::TotalMismatch {
+                client_id: self.client_id,
+                total: self.total_funds(),
+                recomputed,
+            });
+        }
+        anomalies
+    }
+    /// Get the dispute lifecycle outcome of the given transaction.
+    pub(crate) fn dispute_outcome(&self, transaction: TransactionId) -> DisputeOutcome {
+        match self.find_dispute(transaction) {
+            DisputeSate::Disputed(_) => DisputeOutcome::Disputed,
+            DisputeSate::Chargeback => DisputeOutcome::Chargeback,
+            DisputeSate::Resolved => DisputeOutcome::Resolved,
+            DisputeSate::Undisputed if self.resolved_disputes.contains(&transaction) => {
+                DisputeOutcome::Resolved
+            }
+            DisputeSate::Undisputed => DisputeOutcome::Undisputed,
+        }
+    }
+}
+
+/// An inconsistency in the funds of an account
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum AccountAnomaly {
+    #[error("client {client_id}: cached held funds {cached} differ from the held funds {held}")]
+    HeldMismatch {
+        client_id: ClientId,
+        cached: rust_decimal::Decimal,
+        held: rust_decimal::Decimal,
+    },
+    #[error("client {client_id}: total funds {total} differ from the recomputed {recomputed}")]
+    TotalMismatch {
+        client_id: ClientId,
+        total: rust_decimal::Decimal,
+        recomputed: rust_decimal::Decimal,
+    },
 }
 
 impl From<&Account> for AccountLog {
     fn from(acc: &Account) -> Self {
+        let output = |amount| acc.output(amount);
+        let available = output(acc.available_funds());
+        let held = output(acc.held_funds_cache);
         AccountLog::new(
             acc.client_id,
-            acc.available_funds().normalize(),
-            acc.held_funds_cache.round_dp(4).normalize(),
-            acc.total_funds().normalize(),
-            acc.locked,
+            available,
+            held,
+            output(available + held),
+            acc.locked(),
         )
     }
 }
 
-pub(crate) trait AccountInfo {
+pub trait AccountInfo {
     fn client_id(&self) -> ClientId;
     fn available_funds(&self) -> rust_decimal::Decimal;
     fn held_funds(&self) -> rust_decimal::Decimal;
     fn total_funds(&self) -> rust_decimal::Decimal;
+    /// Whether the account is locked, either by a chargeback or as it's frozen.
     fn locked(&self) -> bool;
+    /// Whether the account is locked by a chargeback, rather than only frozen.
+    fn charged_back(&self) -> bool {
+        self.locked()
+    }
+    fn overdraft_limit(&self) -> rust_decimal::Decimal {
+        rust_decimal::Decimal::ZERO
+    }
     fn find_dispute(&self, transaction: TransactionId) -> DisputeSate {
         let _ = transaction;
         DisputeSate::Undisputed
@@ -73,17 +233,23 @@ impl AccountInfo for Account {
         self.client_id
     }
     fn available_funds(&self) -> rust_decimal::Decimal {
-        self.available_funds.round_dp(4)
+        self.round(self.available_funds)
     }
     fn held_funds(&self) -> rust_decimal::Decimal {
-        self.held_funds_cache.round_dp(4)
+        self.round(self.held_funds_cache)
     }
     fn total_funds(&self) -> rust_decimal::Decimal {
         self.held_funds() + self.available_funds()
     }
     fn locked(&self) -> bool {
+        self.locked || self.frozen
+    }
+    fn charged_back(&self) -> bool {
         self.locked
     }
+    fn overdraft_limit(&self) -> rust_decimal::Decimal {
+        self.overdraft_limit
+    }
     fn find_dispute(&self, transaction: TransactionId) -> DisputeSate {
         if let Some(amount) = self.held_funds.get(&transaction) {
             DisputeSate::Disputed(*amount)
@@ -101,33 +267,167 @@ pub(crate) trait SetAccountInfo {
     fn add_held_funds(&mut self, amount: rust_decimal::Decimal, disputer_id: TransactionId);
     fn remove_held_funds(&mut self, disputer_id: TransactionId);
     fn set_locked(&mut self, locked: bool);
+    fn set_frozen(&mut self, frozen: bool);
     fn complete_dispute(&mut self, disputer_id: TransactionId, state: DisputeSate);
 }
 
 impl SetAccountInfo for Account {
     fn set_available_funds(&mut self, amount: rust_decimal::Decimal) {
-        self.available_funds = amount.round_dp(4);
+        self.available_funds = self.round(amount);
     }
     fn add_held_funds(&mut self, amount: rust_decimal::Decimal, disputer_id: TransactionId) {
-        let amount = amount.round_dp(4);
-        self.held_funds.insert(disputer_id, amount);
-        self.held_funds_cache += amount;
+        let amount = self.round(amount);
+        *self.held_funds.entry(disputer_id).or_default() += amount;
+        self.held_funds_cache = self.held_funds.values().sum();
     }
     fn remove_held_funds(&mut self, disputer_id: TransactionId) {
-        if let Some(d) = self.held_funds.remove(&disputer_id) {
-            self.held_funds_cache -= d.round_dp(4);
-        };
+        if self.held_funds.remove(&disputer_id).is_some() {
+            // recompute rather than decrement the cache, which would otherwise carry along any
+            // precision lost while summing, eg: when holding amounts beyond the decimal precision
+            self.held_funds_cache = self.held_funds.values().sum();
+        }
     }
     fn set_locked(&mut self, locked: bool) {
         self.locked = locked;
     }
+    fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
     fn complete_dispute(&mut self, disputer_id: TransactionId, state: DisputeSate) {
         match state {
-            DisputeSate::Undisputed => {}
+            DisputeSate::Undisputed => {
+                self.resolved_disputes.insert(disputer_id);
+            }
             DisputeSate::Disputed(_) => {}
             DisputeSate::Chargeback => {
                 self.completed_disputes.insert(disputer_id, state);
             }
+            DisputeSate::Resolved => {
+                self.resolved_disputes.insert(disputer_id);
+                self.completed_disputes.insert(disputer_id, state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Account, AccountAnomaly, AccountInfo, SetAccountInfo};
+    use crate::{
+        bank::tests::{bank, bank_with},
+        config::{BankConfig, LedgerConfig, Rounding},
+    };
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn anomalies() -> anyhow::Result<()> {
+        let mut bank = bank("./test_data/dispute/report/input.csv".into())?;
+        bank.process()?;
+        assert!(bank.anomalies()?.is_empty());
+
+        // corrupt the held funds cache of client 1, which is holding 2 for tx 3
+        bank.account(1)?.lock().unwrap().held_funds_cache = dec!(3);
+        bank.flush_accounts()?;
+
+        assert_eq!(
+            bank.anomalies()?,
+            vec![
+                AccountAnomaly::HeldMismatch {
+                    client_id: 1,
+                    cached: dec!(3),
+                    held: dec!(2),
+                },
+                AccountAnomaly::TotalMismatch {
+                    client_id: 1,
+                    total: dec!(3),
+                    recomputed: dec!(2),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn peak_total() -> anyhow::Result<()> {
+        let test_folder = std::path::PathBuf::from("./test_data/peak/ok");
+        let config = BankConfig {
+            with_peak: true,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        let buffer = bank.ordered_accounts_balance_buffer()?;
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(buffer, expected);
+
+        // the peak is reached before the withdrawal and is not lowered by it
+        assert_eq!(bank.account(1)?.lock().unwrap().peak_total, dec!(15));
+
+        // the chargeback of the disputed withdrawal moves the held funds back into the available
+        // funds, which must not be sampled halfway through
+        let test_folder = test_folder.with_file_name("chargeback");
+        let config = BankConfig {
+            with_peak: true,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        let buffer = bank.ordered_accounts_balance_buffer()?;
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(buffer, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn held_funds_cache() {
+        for rounding in [
+            Rounding::PerOperation,
+            Rounding::OutputOnly,
+            Rounding::Never,
+        ] {
+            let mut account = Account::new(1, rounding);
+            // interleave disputes and resolves of amounts which don't fit within 4 decimal places
+            for tx in 0..1000u32 {
+                let amount = dec!(0.00005) * rust_decimal::Decimal::from(tx % 7 + 1) + dec!(0.1);
+                account.add_held_funds(amount, tx);
+                if tx % 3 != 0 {
+                    account.remove_held_funds(tx - tx % 3);
+                }
+                let held = account.held_funds.values().sum::<rust_decimal::Decimal>();
+                assert_eq!(account.held_funds_cache, held, "{:?}", rounding);
+            }
+            for tx in 0..1000 {
+                account.remove_held_funds(tx);
+            }
+            assert_eq!(account.held_funds(), dec!(0), "{:?}", rounding);
+        }
+    }
+
+    #[test]
+    fn held_funds_order() {
+        use itertools::Itertools;
+
+        // beyond the decimal precision the sum is rounded as per the order of the held funds
+        let holds = [
+            (1, dec!(0.4)),
+            (2, dec!(0.4)),
+            (3, dec!(50000000000000000000000000000)),
+        ];
+        let mut cached = vec![];
+        for order in holds.iter().permutations(holds.len()) {
+            let mut account = Account::new(1, Rounding::Never);
+            for (tx, amount) in order {
+                account.add_held_funds(*amount, *tx);
+            }
+            assert!(account.anomalies().is_empty());
+            cached.push(account.held_funds_cache);
         }
+        assert!(cached.iter().all_equal(), "{:?}", cached);
     }
 }
@@ -1,92 +1,1425 @@
 use crate::{
-    account::{Account, AccountId, AccountInfo, SetAccountInfo},
+    account::{Account, AccountAnomaly, AccountId, AccountInfo, SetAccountInfo},
     client::ClientId,
-    csv::transaction::TransactionId,
-    transaction::{DisputeSate, TransactionLog},
-    transactions::{BankTransaction, Transaction, TransactionInfo},
+    config::{BankConfig, DuplicatePolicy, DuplicateScope, OutputFormat, SortBy},
+    csv::{
+        account::AccountLog,
+        dispute::{DisputeLog, DisputeLogState},
+        error_log::ErrorLog,
+        event::{AccountEvent, AccountEventKind},
+        statement::StatementLog,
+        transaction::{TransactionId, TransactionType},
+    },
+    error::{HeathError, HeathResult},
+    ledger::BatchMarkers,
+    store::AccountStore,
+    table::write_table,
+    transaction::{DisputeOutcome, DisputeSate, TransactionLog, TransactionState},
+    transactions::{BankTransaction, Transaction, TransactionError, TransactionInfo},
     Ledger,
 };
 use itertools::Itertools;
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, BTreeMap, HashMap},
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
+/// Number of transactions processed between deadline checks
+const DEADLINE_CHECK_INTERVAL: usize = 1000;
+
 /// A bank Account
 /// todo: The way things are this could probably use a Cell instead of a Mutex
 pub(crate) type BankAccount = Arc<Mutex<Account>>;
 
 /// A Bank
-/// It has a ledger of transactions and bank accounts.
+/// It has a ledger of transactions and bank accounts, kept in an `AccountStore`.
 #[derive(Debug)]
-pub(crate) struct Bank {
-    accounts: HashMap<AccountId, BankAccount>,
+pub struct Bank<S = HashMap<AccountId, Account>> {
+    accounts: S,
+    /// Accounts in use by the current transaction, written back into the store once it's applied.
+    open_accounts: HashMap<AccountId, BankAccount>,
+    /// Available and held funds of each account in use when it was opened, to track the funds
+    /// moved.
+    opened_funds: HashMap<AccountId, (rust_decimal::Decimal, rust_decimal::Decimal)>,
+    /// Funds moved by the transactions applied so far.
+    flows: GlobalReconcile,
+    /// The account holding the funds of each dispute, used for cross-client disputes.
+    dispute_holders: HashMap<TransactionId, AccountId>,
+    /// Statement of the configured statement client.
+    statement: Vec<StatementLog>,
+    /// Error log of the ignored transactions, when enabled.
+    error_log: Vec<ErrorLog>,
+    /// Index of the amount bearing transactions applied so far.
+    index: HashMap<(AccountId, TransactionId), TransactionLog>,
+    /// The account of the first indexed transaction with each transaction id, used to find the
+    /// transactions of cross-client disputes.
+    transaction_owners: HashMap<TransactionId, AccountId>,
+    /// Number of dispute lifecycle transactions applied so far.
+    disputes_applied: usize,
+    /// Number of deposits and withdrawals applied so far, per account.
+    amount_transactions: HashMap<AccountId, usize>,
+    /// Number of transactions ignored so far, per category of the reason.
+    skipped: BTreeMap<&'static str, usize>,
+    /// Number of transactions processed so far, including the ignored ones.
+    processed: usize,
+    /// Number of transactions processed so far, per transaction type.
+    processed_by_type: BTreeMap<TransactionType, usize>,
+    /// The batch markers of the ledger being processed, when summarizing batches.
+    batch_markers: Option<BatchMarkers>,
+    /// Summaries of the batches processed so far.
+    batches: Vec<BatchSummary>,
+    /// The dispute lifecycle steps applied so far, per account holding the disputed funds.
+    dispute_timelines: HashMap<AccountId, Vec<DisputeEvent>>,
+    /// The snapshot to resume the processing from, rather than from the start of the ledger.
+    snapshot: Option<BankSnapshot>,
     ledger: Ledger,
+    config: BankConfig,
 }
 
 impl Bank {
-    /// Return a new `Self` with the provided `Ledger`
-    pub(crate) fn new(ledger: Ledger) -> Self {
+    /// Return a new `Self` with the provided `Ledger` and `BankConfig`
+    pub fn new(ledger: Ledger, config: BankConfig) -> Self {
+        Self::with_store(ledger, config, Default::default())
+    }
+    /// Return a new `Self` with the given in-memory transactions applied, without any csv, eg: for
+    /// testing or embedding, after which the balance of the accounts may be queried or written out.
+    /// Its ledger is empty, so processing it again, eg: by `process`, resets the accounts.
+    pub fn from_transactions(
+        transactions: impl IntoIterator<Item = TransactionLog>,
+        config: BankConfig,
+    ) -> HeathResult<Self> {
+        let ledger = Ledger::from_str("", Default::default())?;
+        let mut bank = Self::new(ledger, config);
+        bank.apply(
+            transactions.into_iter().map(Ok).enumerate(),
+            Instant::now(),
+            &mut |_| {},
+            &mut |_, _| {},
+        )?;
+        Ok(bank)
+    }
+}
+
+impl<S: AccountStore> Bank<S> {
+    /// Return a new `Self` with the provided `Ledger`, `BankConfig` and `AccountStore`
+    pub fn with_store(ledger: Ledger, config: BankConfig, accounts: S) -> Self {
         Self {
-            accounts: Default::default(),
+            accounts,
+            open_accounts: Default::default(),
+            opened_funds: Default::default(),
+            flows: Default::default(),
+            dispute_holders: Default::default(),
+            statement: Default::default(),
+            error_log: Default::default(),
+            index: Default::default(),
+            transaction_owners: Default::default(),
+            disputes_applied: 0,
+            amount_transactions: Default::default(),
+            skipped: Default::default(),
+            processed_by_type: Default::default(),
+            processed: 0,
+            batch_markers: None,
+            batches: vec![],
+            dispute_timelines: Default::default(),
+            snapshot: None,
             ledger,
+            config,
         }
     }
+    /// Get the `BankConfig`
+    pub(crate) fn config(&self) -> &BankConfig {
+        &self.config
+    }
     /// Get the BankAccount for the given account_id
     /// If the account does not exist a new default account will be created
-    pub(crate) fn account(&mut self, account_id: AccountId) -> BankAccount {
-        self.accounts
-            .entry(account_id)
-            .or_insert_with(|| Arc::new(Mutex::new(Account::new(account_id))))
-            .clone()
+    /// Changes to the account are only stored once `flush_accounts` is called.
+    pub(crate) fn account(&mut self, account_id: AccountId) -> HeathResult<BankAccount> {
+        if let Some(account) = self.open_accounts.get(&account_id) {
+            return Ok(account.clone());
+        }
+        let account = match self.accounts.get(account_id)? {
+            Some(account) => account,
+            None => Account::new(account_id, self.config.rounding).with_overdraft_limit(
+                self.config
+                    .overdraft_limits
+                    .get(&account_id)
+                    .copied()
+                    .unwrap_or_default(),
+            ),
+        };
+        self.opened_funds.insert(
+            account_id,
+            (account.available_funds(), account.held_funds()),
+        );
+        let account = Arc::new(Mutex::new(account));
+        self.open_accounts.insert(account_id, account.clone());
+        Ok(account)
+    }
+    /// Whether the account of the given account_id exists, without creating it.
+    pub(crate) fn has_account(&self, account_id: AccountId) -> HeathResult<bool> {
+        Ok(
+            self.open_accounts.contains_key(&account_id)
+                || self.accounts.get(account_id)?.is_some(),
+        )
+    }
+    /// Get the change in the total funds of the accounts in use since they were opened
+    fn open_accounts_delta(&self) -> Option<rust_decimal::Decimal> {
+        self.open_accounts.iter().try_fold(
+            rust_decimal::Decimal::ZERO,
+            |delta, (account_id, account)| {
+                let (available, held) = self.opened_funds[account_id];
+                let moved = account
+                    .total_funds()
+                    .checked_sub(available.checked_add(held)?)?;
+                delta.checked_add(moved)
+            },
+        )
+    }
+    /// Emit an `AccountEvent` for each account in use whose funds were changed by the transaction.
+    fn emit_events(&self, transaction: &TransactionLog, event_sink: &mut dyn FnMut(AccountEvent)) {
+        let accounts = self.open_accounts.iter().sorted_by_key(|(id, _)| **id);
+        for (account_id, account) in accounts {
+            let (available, held) = self.opened_funds[account_id];
+            let (resulting_available, resulting_held) =
+                (account.available_funds(), account.held_funds());
+            if (resulting_available, resulting_held) == (available, held) {
+                continue;
+            }
+            let moved = (resulting_available + resulting_held) - (available + held);
+            let (kind, delta) = match transaction {
+                TransactionLog::Deposit { .. } => (AccountEventKind::Credit, moved),
+                TransactionLog::Withdrawal { .. } => (AccountEventKind::Debit, moved),
+                TransactionLog::Dispute { .. } => (AccountEventKind::Hold, resulting_held - held),
+                TransactionLog::Resolve { .. } => {
+                    (AccountEventKind::Release, resulting_held - held)
+                }
+                TransactionLog::Chargeback { .. } => (AccountEventKind::Chargeback, moved),
+                TransactionLog::Adjust { .. } => (AccountEventKind::Adjust, moved),
+                TransactionLog::Transfer { .. } if moved.is_sign_negative() => {
+                    (AccountEventKind::Debit, moved)
+                }
+                TransactionLog::Transfer { .. } => (AccountEventKind::Credit, moved),
+                // unlocking, freezing and unfreezing move no funds
+                TransactionLog::Unlock { .. }
+                | TransactionLog::Freeze { .. }
+                | TransactionLog::Unfreeze { .. } => continue,
+            };
+            event_sink(AccountEvent {
+                client_id: *account_id,
+                tx_id: transaction.transaction_id(),
+                kind,
+                delta,
+                resulting_available,
+                resulting_held,
+            });
+        }
+    }
+    /// Write the accounts in use back into the store
+    pub(crate) fn flush_accounts(&mut self) -> HeathResult<()> {
+        self.opened_funds.clear();
+        for (_, account) in self.open_accounts.drain() {
+            // the transactions are done with the account, so it's moved out rather than cloned
+            let account = match Arc::try_unwrap(account) {
+                Ok(account) => account.into_inner().unwrap(),
+                Err(account) => account.lock().unwrap().clone(),
+            };
+            self.accounts.insert(account)?;
+        }
+        Ok(())
     }
     /// Try to get the TransactionLog for the given transaction_id
-    /// Searches the ledger only up to the chronologically ordered index max_ledger_search
+    /// Only the deposits and withdrawals applied so far are indexed, ie: not those which were
+    /// ignored, eg: a withdrawal of more than the available funds, each once its own row is
+    /// applied, so a dispute, resolve or chargeback may only reference strictly earlier rows:
+    /// never its own row nor a later one, even if a later deposit has the referenced tx id.
     pub(crate) fn transaction(
-        &mut self,
-        max_ledger_search: usize,
+        &self,
         account_id: AccountId,
         transaction_id: TransactionId,
-    ) -> anyhow::Result<Option<TransactionLog>> {
-        Ok(self
-            .ledger
-            .iter()?
-            .take(max_ledger_search)
-            .find(|transaction| {
-                transaction.transaction_id() == transaction_id
-                    && account_id == transaction.client_id()
-            }))
+    ) -> Option<TransactionLog> {
+        self.index.get(&(account_id, transaction_id)).cloned()
+    }
+
+    /// Get the account of the first indexed transaction with the given transaction_id, if any.
+    pub(crate) fn transaction_owner(&self, transaction_id: TransactionId) -> Option<AccountId> {
+        self.transaction_owners.get(&transaction_id).copied()
+    }
+    /// Try to get the TransactionLog disputed by the given transaction_id
+    /// With cross-client disputes the disputed transaction may belong to any client, otherwise
+    /// it must belong to the given account_id.
+    pub(crate) fn disputed_transaction(
+        &self,
+        account_id: AccountId,
+        transaction_id: TransactionId,
+    ) -> Option<TransactionLog> {
+        if !self.config.cross_client_disputes {
+            return self.transaction(account_id, transaction_id);
+        }
+        let owner = self.transaction_owners.get(&transaction_id)?;
+        self.transaction(*owner, transaction_id)
+    }
+    /// Get the BankAccount holding the funds of the given disputed transaction
+    /// With cross-client disputes this is the account which owns the disputed transaction,
+    /// otherwise it's the given account_id.
+    pub(crate) fn dispute_account(
+        &mut self,
+        account_id: AccountId,
+        disputed_tx: &Option<TransactionLog>,
+    ) -> HeathResult<BankAccount> {
+        match disputed_tx {
+            Some(disputed_tx) if self.config.cross_client_disputes => {
+                let holder = *self
+                    .dispute_holders
+                    .entry(disputed_tx.transaction_id())
+                    .or_insert_with(|| disputed_tx.client_id());
+                self.account(holder)
+            }
+            _ => self.account(account_id),
+        }
     }
 
-    /// Get the ordered accounts balance as a String
-    pub(crate) fn ordered_accounts_balance_buffer(&mut self) -> anyhow::Result<String> {
+    /// Apply the ledger transactions into the bank accounts, which are retained to be queried,
+    /// eg: by `account_balance`, or written out, eg: by `write_accounts`.
+    /// If the configured deadline is exceeded processing stops with `HeathError::Cancelled`
+    /// and the accounts are left as computed so far.
+    /// Returns the number of transactions processed and skipped.
+    pub fn process(&mut self) -> HeathResult<ProcessStats> {
+        match self.config.jobs {
+            Some(jobs) if jobs > 1 => self.process_parallel(jobs),
+            _ => self.process_with_events(|_| {}),
+        }
+    }
+    /// Same as `process` but partitioning the transactions by client and applying each partition
+    /// on its own thread, as the accounts of different clients are independent.
+    /// As a partition does not know the transactions of the other partitions' clients, disputes,
+    /// resolves and chargebacks of another client's transaction are ignored as unknown.
+    /// Fails with `HeathError::InvalidConfig` if cross-client disputes, the maximum number of
+    /// disputes or batch summaries are configured, as these span across clients.
+    fn process_parallel(&mut self, jobs: usize) -> HeathResult<ProcessStats> {
+        use rayon::prelude::*;
+        if self.config.cross_client_disputes
+            || self.config.max_disputes.is_some()
+            || self.config.batch_summaries
+            || self.config.duplicate_scope == DuplicateScope::Global
+            || self.snapshot.is_some()
+        {
+            return Err(HeathError::InvalidConfig {
+                reason: "cross-client disputes, max disputes, batch summaries, globally unique \
+                         tx ids and resumed snapshots cannot be processed in parallel"
+                    .to_string(),
+            });
+        }
+        self.reset()?;
+        let started = Instant::now();
+        let mut partitions = vec![vec![]; jobs];
+        for (index, transaction) in self.ledger.iter()?.enumerate() {
+            let transaction = transaction?;
+            if let TransactionLog::Transfer { .. } = transaction {
+                return Err(HeathError::InvalidConfig {
+                    reason: "transfers span across clients and cannot be processed in parallel"
+                        .to_string(),
+                });
+            }
+            partitions[usize::from(transaction.client_id()) % jobs].push((index, transaction));
+        }
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .map_err(|error| HeathError::InvalidConfig {
+                reason: format!("failed to start {} jobs: {}", jobs, error),
+            })?;
+        let (ledger, config) = (&self.ledger, &self.config);
+        let banks = pool.install(|| {
+            partitions
+                .into_par_iter()
+                .map(|partition| {
+                    let mut bank = Bank::new(ledger.clone(), config.clone());
+                    let applied = bank.apply(
+                        partition.into_iter().map(|(index, tx)| (index, Ok(tx))),
+                        started,
+                        &mut |_| {},
+                        &mut |_, _| {},
+                    );
+                    (bank, applied)
+                })
+                .collect::<Vec<_>>()
+        });
+        // merge all the partitions, even on a missed deadline, before failing
+        let mut applied = Ok(());
+        for (bank, partition_applied) in banks {
+            self.merge(bank)?;
+            applied = applied.and(partition_applied);
+        }
+        applied?;
+        Ok(self.stats())
+    }
+    /// Merge the state computed by the `other` bank, which applied the transactions of clients
+    /// disjoint from this bank's.
+    fn merge<O: AccountStore>(&mut self, other: Bank<O>) -> HeathResult<()> {
+        for account in other.accounts.iter() {
+            self.accounts.insert(account?)?;
+        }
+        self.index.extend(other.index);
+        self.transaction_owners.extend(other.transaction_owners);
+        self.amount_transactions.extend(other.amount_transactions);
+        self.dispute_timelines.extend(other.dispute_timelines);
+        self.statement.extend(other.statement);
+        self.error_log.extend(other.error_log);
+        for (category, skipped) in other.skipped {
+            *self.skipped.entry(category).or_default() += skipped;
+        }
+        self.processed += other.processed;
+        for (type_, processed) in other.processed_by_type {
+            *self.processed_by_type.entry(type_).or_default() += processed;
+        }
+        self.disputes_applied += other.disputes_applied;
+        self.flows = self.flows.merge(&other.flows).ok_or(HeathError::Overflow {
+            context: "bank flows",
+        })?;
+        Ok(())
+    }
+    /// Same as `process` but invoking the `event_sink` on each change of an account's funds.
+    pub fn process_with_events(
+        &mut self,
+        mut event_sink: impl FnMut(AccountEvent),
+    ) -> HeathResult<ProcessStats> {
+        self.process_observed(&mut event_sink, &mut |_, _| {})
+    }
+    /// Same as `process` but invoking the `hook` after each transaction is executed, with the
+    /// resulting account of its client, eg: to journal the transactions as they're applied.
+    /// The hook is not invoked for the rows ignored before being executed, eg: malformed rows,
+    /// negative amounts or ignored duplicates, nor for cross-client disputes, resolves and
+    /// chargebacks, which apply to the account owning the disputed transaction rather than to the
+    /// client's. It is invoked, with the unchanged account, for the transactions skipped as
+    /// they're executed, eg: a withdrawal without the funds.
+    pub fn process_with(
+        &mut self,
+        mut hook: impl FnMut(&TransactionLog, &Account),
+    ) -> HeathResult<ProcessStats> {
+        self.process_observed(&mut |_| {}, &mut hook)
+    }
+    /// Process the ledger invoking the `event_sink` on each change of an account's funds and the
+    /// `hook` after each transaction is executed.
+    fn process_observed(
+        &mut self,
+        event_sink: &mut dyn FnMut(AccountEvent),
+        hook: &mut dyn FnMut(&TransactionLog, &Account),
+    ) -> HeathResult<ProcessStats> {
+        self.reset()?;
+        let started = Instant::now();
+        // rows already applied by a loaded snapshot are skipped
+        let resumed = self.processed;
+        let transactions = self.ledger.iter()?;
+        self.track_batches(&transactions);
+        match self.config.pipeline_capacity {
+            None => self.apply(
+                transactions.enumerate().skip(resumed),
+                started,
+                event_sink,
+                hook,
+            )?,
+            Some(capacity) => {
+                // parse the ledger on a separate thread, overlapping the reads with processing
+                let (reader, transactions) = transactions.pipelined(capacity);
+                let applied = self.apply(
+                    transactions.into_iter().enumerate().skip(resumed),
+                    started,
+                    event_sink,
+                    hook,
+                );
+                if let Err(panic) = reader.join() {
+                    std::panic::resume_unwind(panic);
+                }
+                applied?
+            }
+        }
+        Ok(self.stats())
+    }
+    /// Same as `process` but invoking `progress` after each `every` transactions, and once all
+    /// have been processed, with the approximate progress through the ledger input.
+    pub fn process_with_progress(
+        &mut self,
+        every: usize,
+        mut progress: impl FnMut(Progress),
+    ) -> HeathResult<ProcessStats> {
+        self.reset()?;
+        let started = Instant::now();
+        let resumed = self.processed;
+        let total_bytes = self.ledger.len()?;
+        let mut transactions = self.ledger.iter()?;
+        self.track_batches(&transactions);
+        let every = every.max(1);
+        let mut processed = 0;
+        let transactions = std::iter::from_fn(|| {
+            let transaction = transactions.next();
+            let report = match transaction {
+                Some(_) => {
+                    processed += 1;
+                    processed % every == 0
+                }
+                // unless just reported
+                None => processed == 0 || processed % every != 0,
+            };
+            if report {
+                progress(Progress {
+                    processed,
+                    bytes: transactions.position(),
+                    total_bytes,
+                });
+            }
+            transaction
+        });
+        self.apply(
+            transactions.enumerate().skip(resumed),
+            started,
+            &mut |_| {},
+            &mut |_, _| {},
+        )?;
+        Ok(self.stats())
+    }
+    /// Apply a ledger sorted by client, one client at a time, emitting each client's account as
+    /// soon as its transactions end and then dropping it, so that only a single account is kept.
+    /// Disputes may only reference earlier transactions of the same client and, as the accounts
+    /// are dropped, they're not available once processing completes.
+    /// When resuming a previous run the clients up to and including `resume_after` are skipped,
+    /// as their accounts have already been emitted.
+    /// Fails with `HeathError::NotSortedByClient` if the ledger is not sorted by client, or with
+    /// `HeathError::InvalidConfig` if a snapshot was loaded, as its accounts cannot be dropped, or
+    /// if cross-client disputes, globally unique tx ids or transfers are used, as these span
+    /// across clients.
+    pub fn process_sorted_by_client(
+        &mut self,
+        resume_after: Option<ClientId>,
+        mut emit: impl FnMut(AccountLog) -> HeathResult<()>,
+    ) -> HeathResult<ProcessStats> {
+        if self.snapshot.is_some() {
+            return Err(HeathError::InvalidConfig {
+                reason: "a snapshot cannot be resumed when sorted by client".to_string(),
+            });
+        }
+        if self.config.cross_client_disputes
+            || self.config.duplicate_scope == DuplicateScope::Global
+        {
+            return Err(HeathError::InvalidConfig {
+                reason: "cross-client disputes and globally unique tx ids cannot be processed \
+                         sorted by client"
+                    .to_string(),
+            });
+        }
+        self.reset()?;
+        let started = Instant::now();
+        let transactions = self.ledger.iter()?;
+        self.track_batches(&transactions);
+        // the destination of a transfer may belong to an already emitted, or a later, client
+        let transactions = transactions
+            .map(|transaction| match transaction {
+                Ok(TransactionLog::Transfer { .. }) => Err(HeathError::InvalidConfig {
+                    reason: "transfers span across clients and cannot be processed sorted by \
+                             client"
+                        .to_string(),
+                }),
+                transaction => transaction,
+            })
+            .enumerate();
+        let groups = transactions
+            .group_by(|(_, transaction)| transaction.as_ref().ok().map(TransactionInfo::client_id));
+        let mut previous_client = None;
+        for (client_id, transactions) in &groups {
+            let client_id = match client_id {
+                Some(client_id) => client_id,
+                None => {
+                    // a malformed row, which fails to apply
+                    self.apply(transactions, started, &mut |_| {}, &mut |_, _| {})?;
+                    continue;
+                }
+            };
+            if previous_client.is_some_and(|previous| client_id < previous) {
+                return Err(HeathError::NotSortedByClient { client: client_id });
+            }
+            previous_client = Some(client_id);
+            if resume_after.is_some_and(|last| client_id <= last) {
+                continue;
+            }
+
+            self.apply(transactions, started, &mut |_| {}, &mut |_, _| {})?;
+            if let Some(account) = self.accounts.remove(client_id)? {
+                if !self.omitted(&account) {
+                    let log = self.account_log(&account);
+                    if self.config.check_invariants {
+                        check_total(&log)?;
+                    }
+                    emit(log)?;
+                }
+            }
+            // the client's transactions can no longer be referenced
+            let _ = std::mem::take(&mut self.dispute_holders);
+            let _ = std::mem::take(&mut self.index);
+            let _ = std::mem::take(&mut self.transaction_owners);
+            let _ = std::mem::take(&mut self.amount_transactions);
+        }
+        Ok(self.stats())
+    }
+    /// Same as `process` but applying the transactions of the given stream, rather than the
+    /// ledger's, awaiting each one so that an async runtime is not blocked while waiting on them.
+    #[cfg(feature = "tokio")]
+    pub async fn process_stream(
+        &mut self,
+        transactions: impl futures::Stream<Item = TransactionLog>,
+    ) -> HeathResult<ProcessStats> {
+        use futures::StreamExt;
+        self.reset()?;
+        let started = Instant::now();
+        let mut transactions = std::pin::pin!(transactions.enumerate());
+        while let Some((index, transaction)) = transactions.next().await {
+            self.apply(
+                std::iter::once((index, Ok(transaction))),
+                started,
+                &mut |_| {},
+                &mut |_, _| {},
+            )?;
+        }
+        Ok(self.stats())
+    }
+    /// Reset all the state computed from the ledger.
+    fn reset(&mut self) -> HeathResult<()> {
         // Note: if we ever wanted to "commit" the ledger into the accounts we'd have to either
         // trim the ledger or make sure the iterator can not be reset
-        let _ = std::mem::take(&mut self.accounts);
-
-        self.ledger.iter()?.enumerate().for_each(|(index, f)| {
+        self.accounts.clear()?;
+        let _ = std::mem::take(&mut self.open_accounts);
+        let _ = std::mem::take(&mut self.opened_funds);
+        let _ = std::mem::take(&mut self.flows);
+        let _ = std::mem::take(&mut self.dispute_holders);
+        let _ = std::mem::take(&mut self.statement);
+        let _ = std::mem::take(&mut self.error_log);
+        let _ = std::mem::take(&mut self.index);
+        let _ = std::mem::take(&mut self.transaction_owners);
+        self.disputes_applied = 0;
+        let _ = std::mem::take(&mut self.amount_transactions);
+        let _ = std::mem::take(&mut self.skipped);
+        self.processed = 0;
+        let _ = std::mem::take(&mut self.processed_by_type);
+        self.batch_markers = None;
+        let _ = std::mem::take(&mut self.batches);
+        let _ = std::mem::take(&mut self.dispute_timelines);
+        if let Some(snapshot) = self.snapshot.clone() {
+            self.restore(snapshot)?;
+        }
+        Ok(())
+    }
+    /// Restore the state recorded by the snapshot.
+    fn restore(&mut self, snapshot: BankSnapshot) -> HeathResult<()> {
+        for account in snapshot.accounts {
+            self.accounts.insert(account)?;
+        }
+        self.index = snapshot
+            .transactions
+            .into_iter()
+            .map(|tx| ((tx.client_id(), tx.transaction_id()), tx))
+            .collect();
+        self.transaction_owners = snapshot.transaction_owners;
+        self.dispute_holders = snapshot.dispute_holders;
+        self.amount_transactions = snapshot.amount_transactions;
+        self.disputes_applied = snapshot.disputes_applied;
+        self.flows = snapshot.flows;
+        self.processed = snapshot.processed;
+        self.processed_by_type = snapshot.processed_by_type;
+        Ok(())
+    }
+    /// Write a snapshot of the accounts, including their held funds and completed disputes,
+    /// along with the transactions applied so far, as JSON.
+    /// Processing may then be resumed from it, by another bank, with `load_snapshot`.
+    pub fn save_snapshot<W: std::io::Write>(&self, writer: W) -> HeathResult<()> {
+        Ok(serde_json::to_writer(writer, &self.snapshot()?)?)
+    }
+    /// Get a snapshot of the accounts and of the transactions applied so far.
+    pub(crate) fn snapshot(&self) -> HeathResult<BankSnapshot> {
+        Ok(BankSnapshot {
+            processed: self.processed,
+            accounts: self.accounts.iter().collect::<HeathResult<_>>()?,
+            transactions: self.index.values().cloned().collect(),
+            transaction_owners: self.transaction_owners.clone(),
+            dispute_holders: self.dispute_holders.clone(),
+            amount_transactions: self.amount_transactions.clone(),
+            disputes_applied: self.disputes_applied,
+            flows: self.flows.clone(),
+            processed_by_type: self.processed_by_type.clone(),
+        })
+    }
+    /// Load a snapshot written by `save_snapshot`, so that processing resumes from it, skipping
+    /// the ledger rows which were already applied. The number of ignored transactions is not
+    /// part of the snapshot, so only those ignored after resuming are counted.
+    pub fn load_snapshot<R: std::io::Read>(&mut self, reader: R) -> HeathResult<()> {
+        self.resume(serde_json::from_reader(reader)?)
+    }
+    /// Resume processing from the given snapshot, as per `load_snapshot`.
+    pub(crate) fn resume(&mut self, snapshot: BankSnapshot) -> HeathResult<()> {
+        self.snapshot = Some(snapshot);
+        self.reset()
+    }
+    /// Apply the given chronologically ordered transactions, along with their chronological
+    /// ledger index, into the bank accounts.
+    /// Malformed transactions are skipped, as a non-fatal error.
+    fn apply(
+        &mut self,
+        transactions: impl Iterator<Item = (usize, HeathResult<TransactionLog>)>,
+        started: Instant,
+        event_sink: &mut dyn FnMut(AccountEvent),
+        hook: &mut dyn FnMut(&TransactionLog, &Account),
+    ) -> HeathResult<()> {
+        for (index, f) in transactions {
+            let f = match f {
+                Ok(f) => f,
+                Err(error) => {
+                    self.skip_malformed(error)?;
+                    continue;
+                }
+            };
+            // count the rows applied by this bank rather than using the ledger index, as each
+            // partition of a parallel run only sees some of the ledger rows
+            if let Some(deadline) = self.config.deadline {
+                if self.processed > 0
+                    && self.processed.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+                    && started.elapsed() > deadline
+                {
+                    return Err(HeathError::Cancelled {
+                        deadline,
+                        processed: self.processed,
+                    });
+                }
+            }
+            self.processed += 1;
+            *self
+                .processed_by_type
+                .entry(f.transaction_type())
+                .or_default() += 1;
+            if let Some(batch) = self.batch_summary(index) {
+                batch.rows += 1;
+            }
+            if self.config.stop_at_first_chargeback && self.is_charged_back(f.client_id())? {
+                tracing::debug!(transaction=?f, "Account frozen at its first chargeback");
+                self.count_skipped(&f, "after_first_chargeback");
+                continue;
+            }
+            if self.config.no_phantom_on_withdrawal
+                && matches!(f, TransactionLog::Withdrawal { .. })
+                && self.accounts.get(f.client_id())?.is_none()
+            {
+                tracing::debug!(transaction=?f, "Withdrawal for a client without an account");
+                self.count_skipped(&f, "withdrawal_unseen_client");
+                continue;
+            }
+            // a negative deposit would drain the account, and a negative withdrawal, or transfer,
+            // credit it, whereas zero amounts are allowed as they simply move no funds
+            if matches!(
+                f,
+                TransactionLog::Deposit { amount, .. }
+                | TransactionLog::Withdrawal { amount, .. }
+                | TransactionLog::Transfer { amount, .. }
+                    if amount.is_sign_negative() && !amount.is_zero()
+            ) {
+                tracing::debug!(transaction=?f, "Negative amount");
+                self.count_skipped(&f, "negative_amount");
+                continue;
+            }
+            if self.is_duplicate(&f) {
+                match self.config.duplicate_policy {
+                    DuplicatePolicy::Ignore => {
+                        tracing::debug!(transaction=?f, "Duplicate transaction id");
+                        self.count_skipped(&f, "duplicate_transaction");
+                        continue;
+                    }
+                    DuplicatePolicy::Warn => {
+                        tracing::warn!(transaction=?f, "Duplicate transaction id");
+                    }
+                }
+            }
+            if f.transaction_type().is_dispute()
+                && Some(self.disputes_applied) == self.config.max_disputes
+            {
+                tracing::debug!(transaction=?f, "Maximum number of disputes reached");
+                self.count_skipped(&f, "max_disputes");
+                continue;
+            }
+            let dispute_before = if f.transaction_type().is_dispute() {
+                Some(self.dispute_step(f.client_id(), f.transaction_id())?)
+            } else {
+                None
+            };
             // as things stand most "errors"/invalid ops are simply ignored, but they're ignored
             // in the specific transaction as it's the one that knows what it should ignore
-            BankTransaction::new(self, index, &f).execute().unwrap();
+            let mut transaction = BankTransaction::new(self, &f);
+            transaction.execute()?;
+            // only the applied transactions may be disputed, eg: not a rejected withdrawal
+            let applied = transaction.skipped().is_none();
+            let flow = transaction.flow();
+            // as the accounts would, were they rounding the amounts moved
+            let flow = self.config.rounding.operation(flow);
+            let moved = self.open_accounts_delta();
+            let flows = moved.and_then(|moved| self.flows.record(&f, flow, moved));
+            let net = match (moved, self.batch_summary(index)) {
+                (_, None) => Some(None),
+                (moved, Some(batch)) => moved
+                    .and_then(|moved| batch.net.checked_add(moved))
+                    .map(Some),
+            };
+            let (flows, net) = match (flows, net) {
+                (Some(flows), Some(net)) => (flows, net),
+                _ => {
+                    // the accounts are not flushed, so the transaction is rolled back
+                    tracing::debug!(transaction=?f, "Bank funds overflow");
+                    self.open_accounts.clear();
+                    self.opened_funds.clear();
+                    self.count_skipped(&f, "bank_overflow");
+                    continue;
+                }
+            };
+            for account in self.open_accounts.values() {
+                account.lock().unwrap().track_peak();
+            }
+            if let Some(account) = self.open_accounts.get(&f.client_id()) {
+                hook(&f, &account.lock().unwrap());
+            }
+            self.emit_events(&f, event_sink);
+            if let (Some(net), Some(batch)) = (net, self.batch_summary(index)) {
+                batch.net = net;
+            }
+            self.flows = flows;
+            self.flush_accounts()?;
+            // ignored disputes, eg: of an unknown transaction, don't count towards the maximum
+            if applied && f.transaction_type().is_dispute() {
+                self.disputes_applied += 1;
+            }
+
+            if let Some(before) = dispute_before {
+                let (outcome, held) = self.dispute_step(f.client_id(), f.transaction_id())?;
+                if (outcome, held) != before {
+                    let holder = self.dispute_holder(f.client_id(), f.transaction_id());
+                    self.dispute_timelines
+                        .entry(holder)
+                        .or_default()
+                        .push(DisputeEvent {
+                            index,
+                            tx_id: f.transaction_id(),
+                            outcome,
+                            held,
+                        });
+                }
+            }
+
+            if applied
+                && matches!(
+                    f,
+                    TransactionLog::Deposit { .. } | TransactionLog::Withdrawal { .. }
+                )
+            {
+                *self.amount_transactions.entry(f.client_id()).or_default() += 1;
+                self.transaction_owners
+                    .entry(f.transaction_id())
+                    .or_insert_with(|| f.client_id());
+                match self.index.entry((f.client_id(), f.transaction_id())) {
+                    Entry::Occupied(mut indexed) if self.config.merge_duplicate_deposits => {
+                        if indexed.get_mut().merge_deposit(&f).is_none() {
+                            // the duplicate is applied, but not merged into the disputable deposit
+                            tracing::debug!(transaction=?f, "Merged deposit amount overflow");
+                            self.count_skipped(&f, "deposit_merge_overflow");
+                        }
+                    }
+                    entry => {
+                        entry.or_insert_with(|| f.clone());
+                    }
+                }
+            }
+
+            // only the applied transactions are on the statement, eg: not a rejected withdrawal
+            if applied && self.config.statement_client == Some(f.client_id()) {
+                let available_after = self.account(f.client_id())?.available_funds();
+                self.statement.push(StatementLog {
+                    tx_id: f.transaction_id(),
+                    type_: f.transaction_type(),
+                    amount: f.amount(),
+                    available_after,
+                });
+                self.flush_accounts()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the deposit or withdrawal reuses the tx id of an earlier one, within the configured
+    /// `DuplicateScope`.
+    fn is_duplicate(&self, transaction: &TransactionLog) -> bool {
+        if !matches!(
+            transaction,
+            TransactionLog::Deposit { .. } | TransactionLog::Withdrawal { .. }
+        ) {
+            return false;
+        }
+        match self.config.duplicate_scope {
+            DuplicateScope::Client => self
+                .index
+                .contains_key(&(transaction.client_id(), transaction.transaction_id())),
+            DuplicateScope::Global => self
+                .transaction_owners
+                .contains_key(&transaction.transaction_id()),
+        }
+    }
+
+    /// Track the batch markers found by the ledger iterator, if summarizing batches.
+    fn track_batches(&mut self, transactions: &crate::ledger::LedgerIter) {
+        if self.config.batch_summaries {
+            self.batch_markers = Some(transactions.batch_markers());
+        }
+    }
+    /// Get the summary of the batch which the transaction with the given ledger index belongs to,
+    /// if any.
+    fn batch_summary(&mut self, index: usize) -> Option<&mut BatchSummary> {
+        let markers = self.batch_markers.as_ref()?.lock().unwrap();
+        let marker = markers.iter().rev().find(|marker| marker.start <= index)?;
+        if self.batches.last().map(|batch| batch.start) != Some(marker.start) {
+            self.batches.push(BatchSummary {
+                label: marker.label.clone(),
+                start: marker.start,
+                rows: 0,
+                net: rust_decimal::Decimal::ZERO,
+            });
+        }
+        self.batches.last_mut()
+    }
+    /// Get the summaries of the batches processed, in ledger order.
+    pub fn batch_summaries(&self) -> &[BatchSummary] {
+        &self.batches
+    }
+
+    /// Whether the stored account of the given client is locked by a chargeback.
+    fn is_charged_back(&self, account_id: AccountId) -> HeathResult<bool> {
+        Ok(self
+            .accounts
+            .get(account_id)?
+            .is_some_and(|account| account.charged_back()))
+    }
+
+    /// Count a transaction ignored for the given category of reason, recording it in the error log
+    /// when enabled.
+    pub(crate) fn count_skipped(&mut self, transaction: &TransactionLog, category: &'static str) {
+        *self.skipped.entry(category).or_default() += 1;
+        if self.config.error_log {
+            self.error_log.push(ErrorLog {
+                line: transaction.line(),
+                client: Some(transaction.client_id()),
+                tx: Some(transaction.transaction_id()),
+                reason: category,
+            });
+        }
+    }
+    /// Skip a malformed ledger row, ie: which failed to parse or is an invalid transaction, as a
+    /// non-fatal error, recording it in the error log when enabled.
+    /// Any other error is returned, as it's fatal.
+    fn skip_malformed(&mut self, error: HeathError) -> HeathResult<()> {
+        let line = match &error {
+            HeathError::Parse { line, .. } | HeathError::InvalidTransaction { line, .. } => *line,
+            _ => return Err(error),
+        };
+        let error = TransactionError::Malformed(error);
+        tracing::debug!(error=%error, "non-fatal error occurred");
+        *self.skipped.entry("malformed_row").or_default() += 1;
+        if self.config.error_log {
+            self.error_log.push(ErrorLog {
+                line,
+                client: None,
+                tx: None,
+                reason: "malformed_row",
+            });
+        }
+        Ok(())
+    }
+    /// Get the number of transactions ignored for the given category of reason.
+    #[allow(dead_code)]
+    pub(crate) fn skipped(&self, category: &str) -> usize {
+        self.skipped.get(category).copied().unwrap_or_default()
+    }
+
+    /// Get the number of transactions processed and skipped so far.
+    pub fn stats(&self) -> ProcessStats {
+        ProcessStats {
+            processed: self.processed,
+            skipped: self.skipped.values().sum(),
+            processed_by_type: self.processed_by_type.clone(),
+            skipped_by_category: self.skipped.clone(),
+        }
+    }
+
+    /// Get the indexed deposits and withdrawals, ordered by client and transaction.
+    pub fn transaction_index(&self) -> Vec<&TransactionLog> {
+        self.index
+            .iter()
+            .sorted_by_key(|(key, _)| *key)
+            .map(|(_, transaction)| transaction)
+            .collect()
+    }
+
+    /// Get the state of the given client's transaction after processing.
+    pub fn transaction_state(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> HeathResult<TransactionState> {
+        let applied = self.index.contains_key(&(client_id, transaction_id));
+        let (dispute, _) = self.dispute_step(client_id, transaction_id)?;
+        Ok(TransactionState { applied, dispute })
+    }
+    /// Get the account holding the funds of the given client's disputed transaction.
+    fn dispute_holder(&self, client_id: ClientId, transaction_id: TransactionId) -> AccountId {
+        // with cross-client disputes the funds may be held by another account
+        match self.dispute_holders.get(&transaction_id) {
+            Some(holder) if self.config.cross_client_disputes => *holder,
+            _ => client_id,
+        }
+    }
+    /// Get the dispute outcome of the given client's transaction and the funds held for it.
+    fn dispute_step(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> HeathResult<(DisputeOutcome, rust_decimal::Decimal)> {
+        let holder = self.dispute_holder(client_id, transaction_id);
+        Ok(match self.accounts.get(holder)? {
+            Some(account) => {
+                let held = match account.find_dispute(transaction_id) {
+                    DisputeSate::Disputed(held) => held,
+                    _ => rust_decimal::Decimal::ZERO,
+                };
+                (account.dispute_outcome(transaction_id), held)
+            }
+            None => (DisputeOutcome::Undisputed, rust_decimal::Decimal::ZERO),
+        })
+    }
+    /// Get the dispute lifecycle steps applied to the transactions whose funds are held by the
+    /// given client's account, in ledger order.
+    /// This is only available through the library, there's no command line output for it.
+    pub fn account_dispute_timeline(&self, client_id: ClientId) -> Vec<DisputeEvent> {
+        self.dispute_timelines
+            .get(&client_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Get the clients whose accounts exist without any applied deposit or withdrawal, ie: only
+    /// because of disputes, resolves or chargebacks referencing them.
+    pub fn orphan_accounts(&self) -> HeathResult<Vec<ClientId>> {
+        let mut orphans = vec![];
+        for account in self.accounts.iter() {
+            let client_id = account?.client_id();
+            if !self.amount_transactions.contains_key(&client_id) {
+                orphans.push(client_id);
+            }
+        }
+        orphans.sort_unstable();
+        Ok(orphans)
+    }
+
+    /// Get the clients whose accounts are locked, ie: by a chargeback or frozen, in sorted order.
+    pub fn locked_clients(&self) -> HeathResult<Vec<ClientId>> {
+        let mut locked = vec![];
+        for account in self.accounts.iter() {
+            let account = account?;
+            if account.locked() {
+                locked.push(account.client_id());
+            }
+        }
+        locked.sort_unstable();
+        Ok(locked)
+    }
+
+    /// Get the error log of the transactions ignored so far, if enabled.
+    pub fn error_log(&self) -> &[ErrorLog] {
+        &self.error_log
+    }
+    /// Get the statement recorded for the configured statement client.
+    pub fn statement(&self) -> &[StatementLog] {
+        &self.statement
+    }
+
+    /// Get the total funds held across all bank accounts.
+    /// Fails with `HeathError::Overflow` if the sum exceeds the `rust_decimal::Decimal` bounds.
+    pub fn total_funds(&self) -> HeathResult<rust_decimal::Decimal> {
+        self.accounts
+            .iter()
+            .try_fold(rust_decimal::Decimal::ZERO, |total, account| {
+                total
+                    .checked_add(account?.total_funds())
+                    .ok_or(HeathError::Overflow {
+                        context: "bank total funds",
+                    })
+            })
+    }
+
+    /// Reconcile the funds moved by the applied transactions against the total funds held across
+    /// all bank accounts.
+    pub fn reconcile_global(&self) -> HeathResult<GlobalReconcile> {
+        Ok(GlobalReconcile {
+            actual: self.total_funds()?,
+            ..self.flows.clone()
+        })
+    }
+
+    /// Get the anomalies of all the bank accounts, ordered by client.
+    pub fn anomalies(&self) -> HeathResult<Vec<AccountAnomaly>> {
+        let mut anomalies = vec![];
+        for account in self.accounts.iter() {
+            anomalies.extend(account?.anomalies());
+        }
+        anomalies.sort_by_key(|anomaly| match anomaly {
+            AccountAnomaly::HeldMismatch { client_id, .. } => (*client_id, 0),
+            AccountAnomaly::TotalMismatch { client_id, .. } => (*client_id, 1),
         });
-        let mut w = csv::Writer::from_writer(vec![]);
-        for account in self
+        Ok(anomalies)
+    }
+
+    /// Get a report of all ongoing and charged back disputes, ordered by client and transaction.
+    pub fn dispute_report(&self) -> HeathResult<Vec<DisputeLog>> {
+        let mut report = vec![];
+        for account in self.accounts.iter() {
+            let account = account?;
+            let client_id = account.client_id();
+            for (tx_id, state) in account.disputes() {
+                let (state, held_now) = match state {
+                    DisputeSate::Disputed(amount) => (DisputeLogState::Disputed, amount),
+                    DisputeSate::Chargeback => {
+                        (DisputeLogState::Chargeback, rust_decimal::Decimal::ZERO)
+                    }
+                    DisputeSate::Undisputed | DisputeSate::Resolved => continue,
+                };
+                let original_amount = self
+                    .transaction(client_id, tx_id)
+                    .and_then(|tx| tx.amount());
+                report.push(DisputeLog {
+                    client_id,
+                    tx_id,
+                    state,
+                    original_amount,
+                    held_now,
+                });
+            }
+        }
+        report.sort_by_key(|entry| (entry.client_id, entry.tx_id));
+        Ok(report)
+    }
+
+    /// Process the ledger and get the ordered accounts balance as a String
+    pub fn ordered_accounts_balance_buffer(&mut self) -> HeathResult<String> {
+        let mut buffer = vec![];
+        self.write_ordered_accounts_balance(&mut buffer, 0)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+    /// Process the ledger and stream the ordered accounts balance into the writer, flushing it
+    /// every `flush_every` accounts, unless 0, and once all are written.
+    /// Returns the number of accounts written.
+    pub fn write_ordered_accounts_balance<W: std::io::Write>(
+        &mut self,
+        w: W,
+        flush_every: usize,
+    ) -> HeathResult<usize> {
+        self.process()?;
+        self.write_accounts_flushing(w, flush_every)
+    }
+
+    /// Whether the account is left out of the output, when omitting the empty accounts, as it has
+    /// no funds, is not locked and never had a deposit or withdrawal applied.
+    fn omitted(&self, account: &Account) -> bool {
+        self.config.omit_empty
+            && !self.amount_transactions.contains_key(&account.client_id())
+            && account.available_funds().is_zero()
+            && account.held_funds().is_zero()
+            && !account.locked()
+    }
+    /// Get the output row of the given account, as per the configuration.
+    fn account_log(&self, account: &Account) -> AccountLog {
+        let log = if self.config.with_peak {
+            account.to_csv_with_peak()
+        } else {
+            account.to_csv()
+        };
+        let log = log.with_bool_format(self.config.bool_format);
+        match (self.config.output_precision, self.config.fixed_decimals) {
+            (None, false) => log,
+            (precision, fixed) => log.with_precision(precision.unwrap_or(4), fixed),
+        }
+    }
+    /// Get the balance of the given client's account, as processed so far, if it exists.
+    pub fn account_balance(&self, client_id: ClientId) -> HeathResult<Option<AccountLog>> {
+        Ok(self
+            .accounts
+            .get(client_id)?
+            .map(|account| self.account_log(&account)))
+    }
+    /// Get the balance of the accounts processed so far, ordered as configured, by client unless
+    /// otherwise set.
+    pub fn ordered_accounts(&self) -> HeathResult<Vec<AccountLog>> {
+        let mut accounts = self
             .accounts
             .iter()
-            .map(|a| a.1.lock().unwrap().to_csv())
-            .sorted_by(|a, b| a.client_id().cmp(&b.client_id()))
-        {
-            w.serialize(account)?;
+            .filter_ok(|a| !self.omitted(a))
+            .map(|a| a.map(|a| self.account_log(&a)))
+            .collect::<HeathResult<Vec<_>>>()?;
+        accounts.sort_by_key(|a| a.client_id());
+        if self.config.assert_unique_clients {
+            assert_unique_clients(&accounts)?;
         }
-        let _ = std::mem::take(&mut self.accounts);
+        if self.config.check_invariants {
+            accounts.iter().try_for_each(check_total)?;
+        }
+        // stable, so that the accounts with equal funds remain ordered by client
+        accounts.sort_by(|a, b| {
+            let ordering = match self.config.sort_by {
+                SortBy::Client => a.client_id().cmp(&b.client_id()),
+                SortBy::Available => a.available_funds().cmp(&b.available_funds()),
+                SortBy::Held => a.held_funds().cmp(&b.held_funds()),
+                SortBy::Total => a.total_funds().cmp(&b.total_funds()),
+            };
+            if self.config.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        Ok(accounts)
+    }
 
-        Ok(String::from_utf8(w.into_inner()?)?)
+    /// Get the ordered balance of the accounts processed so far as a String
+    pub fn accounts_balance_buffer(&self) -> HeathResult<String> {
+        let mut buffer = vec![];
+        self.write_accounts(&mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+    /// Stream the balance of the accounts processed so far, ordered by client, into the writer
+    /// in the configured `OutputFormat`.
+    /// Returns the number of accounts written.
+    pub fn write_accounts<W: std::io::Write>(&self, writer: W) -> HeathResult<usize> {
+        self.write_accounts_flushing(writer, 0)
+    }
+    /// Same as `write_accounts` but flushing the csv writer every `flush_every` accounts, unless 0,
+    /// so that a long write is observable as it progresses.
+    /// The json and table outputs are only flushed once all accounts are written.
+    pub fn write_accounts_flushing<W: std::io::Write>(
+        &self,
+        mut writer: W,
+        flush_every: usize,
+    ) -> HeathResult<usize> {
+        let accounts = self.ordered_accounts()?;
+        match self.config.output_format {
+            OutputFormat::Csv => {
+                let mut w = csv::Writer::from_writer(writer);
+                for (written, account) in accounts.iter().enumerate() {
+                    w.serialize(account)?;
+                    if flush_every > 0 && (written + 1) % flush_every == 0 {
+                        w.flush()?;
+                    }
+                }
+                w.flush()?;
+            }
+            OutputFormat::Json => {
+                serde_json::to_writer(&mut writer, &accounts)?;
+                writeln!(writer)?;
+            }
+            OutputFormat::Table => {
+                let decimals = self.config.output_precision.unwrap_or(4);
+                write_table(&accounts, decimals, self.config.bool_format, &mut writer)?;
+            }
+        }
+        Ok(accounts.len())
+    }
+}
+
+/// Bank-wide reconciliation of the funds moved by the applied transactions
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GlobalReconcile {
+    /// Funds credited by deposits.
+    pub deposited: rust_decimal::Decimal,
+    /// Funds debited by withdrawals, net of the funds of disputed withdrawals, which are credited
+    /// back and held in the accounts until their disputes are resolved.
+    pub withdrawn: rust_decimal::Decimal,
+    /// Funds removed by chargebacks.
+    pub charged_back: rust_decimal::Decimal,
+    /// Funds added, or removed, by adjustments.
+    pub adjusted: rust_decimal::Decimal,
+    /// Funds moved in the accounts beyond those moved by the transactions' own amounts, eg: by a
+    /// dispute, which should only ever move funds within an account.
+    pub unaccounted: rust_decimal::Decimal,
+    /// The actual total funds held across all bank accounts.
+    pub actual: rust_decimal::Decimal,
+}
+impl GlobalReconcile {
+    /// The total funds expected from the net deposits, withdrawals, chargebacks and adjustments.
+    pub fn expected(&self) -> rust_decimal::Decimal {
+        self.deposited - self.withdrawn - self.charged_back + self.adjusted
+    }
+    /// Whether the actual total funds match the expected ones.
+    pub fn matches(&self) -> bool {
+        self.actual == self.expected()
+    }
+    /// Get the flows once the given transaction is recorded, as per the funds it `flow`ed into
+    /// the bank by its own amounts, with any difference to the funds it `moved` in the accounts
+    /// being unaccounted, or `None` if they overflow the `rust_decimal::Decimal` bounds.
+    fn record(
+        &self,
+        transaction: &TransactionLog,
+        flow: rust_decimal::Decimal,
+        moved: rust_decimal::Decimal,
+    ) -> Option<Self> {
+        let mut flows = self.clone();
+        match transaction {
+            TransactionLog::Deposit { .. } => {
+                flows.deposited = flows.deposited.checked_add(flow)?
+            }
+            // the disputed funds of a withdrawal are credited back into the account, until the
+            // dispute is resolved
+            TransactionLog::Withdrawal { .. }
+            | TransactionLog::Dispute { .. }
+            | TransactionLog::Resolve { .. } => {
+                flows.withdrawn = flows.withdrawn.checked_sub(flow)?
+            }
+            TransactionLog::Chargeback { .. } => {
+                flows.charged_back = flows.charged_back.checked_sub(flow)?
+            }
+            TransactionLog::Adjust { .. } => flows.adjusted = flows.adjusted.checked_add(flow)?,
+            // transfers only move funds between accounts
+            TransactionLog::Transfer { .. }
+            | TransactionLog::Unlock { .. }
+            | TransactionLog::Freeze { .. }
+            | TransactionLog::Unfreeze { .. } => {}
+        }
+        flows.unaccounted = flows.unaccounted.checked_add(moved.checked_sub(flow)?)?;
+        Some(flows)
+    }
+    /// Get the sum of these flows and the `other` flows, or `None` if they overflow the
+    /// `rust_decimal::Decimal` bounds.
+    fn merge(&self, other: &Self) -> Option<Self> {
+        Some(Self {
+            deposited: self.deposited.checked_add(other.deposited)?,
+            withdrawn: self.withdrawn.checked_add(other.withdrawn)?,
+            charged_back: self.charged_back.checked_add(other.charged_back)?,
+            adjusted: self.adjusted.checked_add(other.adjusted)?,
+            unaccounted: self.unaccounted.checked_add(other.unaccounted)?,
+            actual: self.actual,
+        })
+    }
+}
+
+/// Progress of the processing through the ledger
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Number of transactions read so far.
+    pub processed: usize,
+    /// Number of bytes of the ledger input read so far.
+    pub bytes: u64,
+    /// Size of the ledger input in bytes, if known, ie: not for gzip compressed input.
+    pub total_bytes: Option<u64>,
+}
+impl Progress {
+    /// Get the approximate percent of the ledger input read so far, if its size is known.
+    pub fn percent(&self) -> Option<f64> {
+        match self.total_bytes {
+            Some(0) => Some(100.0),
+            Some(total) => Some(self.bytes as f64 * 100.0 / total as f64),
+            None => None,
+        }
+    }
+}
+
+/// The state of a bank after applying the first `processed` transactions of its ledger
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct BankSnapshot {
+    /// Number of ledger transactions applied, which are skipped when resuming.
+    processed: usize,
+    accounts: Vec<Account>,
+    /// The indexed deposits and withdrawals, which may still be disputed.
+    transactions: Vec<TransactionLog>,
+    transaction_owners: HashMap<TransactionId, AccountId>,
+    dispute_holders: HashMap<TransactionId, AccountId>,
+    amount_transactions: HashMap<AccountId, usize>,
+    disputes_applied: usize,
+    flows: GlobalReconcile,
+    processed_by_type: BTreeMap<TransactionType, usize>,
+}
+
+/// Number of transactions processed by the bank
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessStats {
+    /// Transactions read from the ledger, including the skipped ones.
+    pub processed: usize,
+    /// Transactions ignored, for any reason, including as non-fatal errors.
+    pub skipped: usize,
+    /// Transactions read from the ledger, per transaction type.
+    pub processed_by_type: BTreeMap<TransactionType, usize>,
+    /// Transactions ignored, per category of the reason, eg: withdrawal_insufficient_funds.
+    pub skipped_by_category: BTreeMap<&'static str, usize>,
+}
+
+/// Summary of a named batch of transactions
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchSummary {
+    /// The batch label.
+    pub label: String,
+    /// Ledger index of the first transaction of the batch.
+    start: usize,
+    /// Transactions within the batch, including the skipped ones.
+    pub rows: usize,
+    /// Net funds moved into the accounts by the batch's transactions.
+    pub net: rust_decimal::Decimal,
+}
+
+/// A step in the dispute lifecycle of a transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisputeEvent {
+    /// Ledger index of the dispute, resolve or chargeback.
+    pub index: usize,
+    /// The disputed transaction.
+    pub tx_id: TransactionId,
+    /// The transaction's dispute outcome after the step.
+    pub outcome: DisputeOutcome,
+    /// The funds held for the transaction after the step.
+    pub held: rust_decimal::Decimal,
+}
+
+/// Check that the output total of the `account` is its output available plus held funds,
+/// recomputing it rather than trusting it.
+fn check_total(account: &AccountLog) -> HeathResult<()> {
+    let (available, held, total) = (
+        account.available_funds(),
+        account.held_funds(),
+        account.total_funds(),
+    );
+    if available.checked_add(held) == Some(total) {
+        Ok(())
+    } else {
+        Err(HeathError::TotalMismatch {
+            client: account.client_id(),
+            available,
+            held,
+            total,
+        })
+    }
+}
+
+/// Check that the client ordered `accounts` contain exactly one row per client.
+fn assert_unique_clients(accounts: &[AccountLog]) -> HeathResult<()> {
+    match accounts
+        .iter()
+        .tuple_windows()
+        .find(|(a, b)| a.client_id() == b.client_id())
+    {
+        Some((duplicate, _)) => Err(HeathError::DuplicateClient {
+            client: duplicate.client_id(),
+        }),
+        None => Ok(()),
     }
 }
 
 impl SetAccountInfo for BankAccount {
     fn set_available_funds(&mut self, amount: rust_decimal::Decimal) {
-        self.lock().unwrap().set_available_funds(amount.round_dp(4))
+        self.lock().unwrap().set_available_funds(amount)
     }
     fn add_held_funds(&mut self, amount: rust_decimal::Decimal, disputer_id: TransactionId) {
         self.lock().unwrap().add_held_funds(amount, disputer_id)
@@ -97,6 +1430,9 @@ impl SetAccountInfo for BankAccount {
     fn set_locked(&mut self, locked: bool) {
         self.lock().unwrap().set_locked(locked)
     }
+    fn set_frozen(&mut self, frozen: bool) {
+        self.lock().unwrap().set_frozen(frozen)
+    }
     fn complete_dispute(&mut self, disputer_id: TransactionId, state: DisputeSate) {
         self.lock().unwrap().complete_dispute(disputer_id, state)
     }
@@ -118,6 +1454,12 @@ impl AccountInfo for BankAccount {
     fn locked(&self) -> bool {
         self.lock().unwrap().locked()
     }
+    fn charged_back(&self) -> bool {
+        self.lock().unwrap().charged_back()
+    }
+    fn overdraft_limit(&self) -> rust_decimal::Decimal {
+        self.lock().unwrap().overdraft_limit()
+    }
     fn find_dispute(&self, transaction: TransactionId) -> DisputeSate {
         self.lock().unwrap().find_dispute(transaction)
     }
@@ -125,22 +1467,1096 @@ impl AccountInfo for BankAccount {
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use crate::{Bank, Ledger};
+    use crate::{
+        account::{Account, AccountId, AccountInfo, SetAccountInfo},
+        config::{BankConfig, LedgerConfig},
+        error::{HeathError, HeathResult},
+        store::AccountStore,
+        Bank, Ledger,
+    };
+    use itertools::Itertools;
+    use std::collections::HashMap;
 
     /// Get a Bank usable for testing
     pub(crate) fn bank(test_file: std::path::PathBuf) -> anyhow::Result<Bank> {
-        let ledger = Ledger::from_path(test_file)?;
-        let bank = Bank::new(ledger);
+        bank_with(test_file, LedgerConfig::default(), BankConfig::default())
+    }
+    /// Get a Bank with the given `LedgerConfig` and `BankConfig` usable for testing
+    pub(crate) fn bank_with(
+        test_file: std::path::PathBuf,
+        ledger_config: LedgerConfig,
+        config: BankConfig,
+    ) -> anyhow::Result<Bank> {
+        let ledger = Ledger::from_path(test_file, ledger_config)?;
+        let bank = Bank::new(ledger, config);
         Ok(bank)
     }
+    /// Run `f` with its logs, up to the debug level, captured rather than written out.
+    /// Returns the result of `f` along with the captured logs.
+    pub(crate) fn capture_logs<R>(f: impl FnOnce() -> R) -> anyhow::Result<(R, String)> {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let logs = Capture::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let result = tracing::subscriber::with_default(subscriber, f);
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone())?;
+        Ok((result, logs))
+    }
     /// Test that the folder's test input and output succeed:
     /// The input is read into the bank which then returns the actual output.
     /// Returns a tuple with the expected output and the actual output.
     pub(crate) fn test(tests_folder: &std::path::Path) -> anyhow::Result<(String, String)> {
+        test_with(tests_folder, LedgerConfig::default(), BankConfig::default())
+    }
+    /// Same as `test` but with the given `LedgerConfig` and `BankConfig`.
+    pub(crate) fn test_with(
+        tests_folder: &std::path::Path,
+        ledger_config: LedgerConfig,
+        config: BankConfig,
+    ) -> anyhow::Result<(String, String)> {
         let expected_output = std::fs::read_to_string(tests_folder.join("output.csv"))?;
-        let mut bank = bank(tests_folder.join("input.csv"))?;
+        let mut bank = bank_with(tests_folder.join("input.csv"), ledger_config, config)?;
 
         let output = bank.ordered_accounts_balance_buffer()?;
         Ok((expected_output.trim().into(), output.trim().into()))
     }
+
+    #[test]
+    fn total_funds() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/dispute/ok");
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+
+        let accounts_total = bank
+            .accounts
+            .values()
+            .map(|a| a.total_funds())
+            .sum::<rust_decimal::Decimal>();
+        assert_eq!(accounts_total, rust_decimal_macros::dec!(5));
+        assert_eq!(bank.total_funds()?, accounts_total);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_report() -> anyhow::Result<()> {
+        use crate::csv::dispute::{DisputeLog, DisputeLogState};
+        use rust_decimal_macros::dec;
+
+        let test_folder = std::path::Path::new("./test_data/dispute/report");
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+
+        let report = bank.dispute_report()?;
+        assert_eq!(
+            report,
+            vec![
+                DisputeLog {
+                    client_id: 1,
+                    tx_id: 1,
+                    state: DisputeLogState::Chargeback,
+                    original_amount: Some(dec!(1)),
+                    held_now: dec!(0),
+                },
+                DisputeLog {
+                    client_id: 1,
+                    tx_id: 3,
+                    state: DisputeLogState::Disputed,
+                    original_amount: Some(dec!(2)),
+                    held_now: dec!(2),
+                },
+                DisputeLog {
+                    client_id: 2,
+                    tx_id: 2,
+                    state: DisputeLogState::Disputed,
+                    original_amount: Some(dec!(2.12345)),
+                    held_now: dec!(2.1234),
+                },
+            ]
+        );
+
+        // only one of the account's disputes is resolved, the others are still reported
+        let test_folder = std::path::Path::new("./test_data/dispute/report_partial");
+        let mut bank = self::bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+        let report = bank
+            .dispute_report()?
+            .into_iter()
+            .map(|entry| (entry.client_id, entry.tx_id, entry.state, entry.held_now))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            report,
+            vec![
+                (1, 1, DisputeLogState::Disputed, dec!(1)),
+                (1, 3, DisputeLogState::Disputed, dec!(3)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/dispute/report");
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        let expected = bank.ordered_accounts_balance_buffer()?;
+
+        let config = BankConfig {
+            pipeline_capacity: Some(2),
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        let output = bank.ordered_accounts_balance_buffer()?;
+        assert_eq!(expected, output);
+        Ok(())
+    }
+
+    #[test]
+    fn total_funds_overflow() -> anyhow::Result<()> {
+        let mut bank = bank("./test_data/deposit/ok/input.csv".into())?;
+        bank.account(1)?
+            .set_available_funds(rust_decimal::Decimal::MAX);
+        bank.account(2)?
+            .set_available_funds(rust_decimal::Decimal::MAX);
+        bank.flush_accounts()?;
+
+        let error = bank.total_funds().unwrap_err();
+        assert!(matches!(error, HeathError::Overflow { .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn statement() -> anyhow::Result<()> {
+        use crate::csv::{statement::StatementLog, transaction::TransactionType};
+        use rust_decimal_macros::dec;
+
+        let test_folder = std::path::Path::new("./test_data/dispute/report");
+        let config = BankConfig {
+            statement_client: Some(1),
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        bank.process()?;
+
+        let line = |tx_id, type_, amount, available_after| StatementLog {
+            tx_id,
+            type_,
+            amount,
+            available_after,
+        };
+        assert_eq!(
+            bank.statement(),
+            &[
+                line(1, TransactionType::Deposit, Some(dec!(1)), dec!(1)),
+                line(3, TransactionType::Deposit, Some(dec!(2)), dec!(3)),
+                line(1, TransactionType::Dispute, None, dec!(2)),
+                line(3, TransactionType::Dispute, None, dec!(0)),
+                line(1, TransactionType::Chargeback, None, dec!(0)),
+            ]
+        );
+
+        // the withdrawal of tx 5 exceeds the available funds, so it's not on the statement
+        let test_folder = std::path::Path::new("./test_data/withdrawal/no_funds");
+        let config = BankConfig {
+            statement_client: Some(2),
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        bank.process()?;
+        assert_eq!(
+            bank.statement(),
+            &[line(2, TransactionType::Deposit, Some(dec!(2)), dec!(2))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn transaction_index() -> anyhow::Result<()> {
+        use crate::transactions::TransactionInfo;
+
+        let test_folder = std::path::Path::new("./test_data/withdrawal/partial");
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+
+        let index = bank
+            .transaction_index()
+            .into_iter()
+            .map(|tx| (tx.client_id(), tx.transaction_id(), tx.amount()))
+            .collect::<Vec<_>>();
+        let amount = |amount: &str| amount.parse().ok();
+        // the withdrawal of tx 3 exceeds the available funds, so it's not indexed
+        assert_eq!(
+            index,
+            vec![
+                (1, 1, amount("2")),
+                (2, 2, amount("2")),
+                (2, 4, amount("1")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn write_ordered_accounts_balance() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/dispute/ok");
+        let mut sink = Vec::<u8>::new();
+        let written =
+            bank(test_folder.join("input.csv"))?.write_ordered_accounts_balance(&mut sink, 0)?;
+
+        let expected = std::fs::read(test_folder.join("output.csv"))?;
+        assert_eq!(sink, expected);
+        assert_eq!(written, 2);
+        Ok(())
+    }
+
+    /// A `Write` which records how many times it was flushed.
+    #[derive(Default)]
+    struct FlushCounter {
+        bytes: Vec<u8>,
+        flushes: usize,
+    }
+    impl std::io::Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.bytes.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_flush_every() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/sort_by/total_desc");
+        let flushes = |flush_every| -> anyhow::Result<usize> {
+            let mut counter = FlushCounter::default();
+            let written = bank(test_folder.join("input.csv"))?
+                .write_ordered_accounts_balance(&mut counter, flush_every)?;
+            assert_eq!(written, 3);
+            assert_eq!(counter.bytes.iter().filter(|b| **b == b'\n').count(), 4);
+            Ok(counter.flushes)
+        };
+        // the writer is always flushed once all accounts are written
+        let once = flushes(0)?;
+        assert!(once > 0);
+        assert_eq!(flushes(1)?, once + 3);
+        assert_eq!(flushes(2)?, once + 1);
+        assert_eq!(flushes(3)?, once + 1);
+        assert_eq!(flushes(4)?, once);
+        Ok(())
+    }
+
+    #[test]
+    fn account_balance() -> anyhow::Result<()> {
+        let mut bank = bank("./test_data/deposit/ok/input.csv".into())?;
+        assert!(bank.account_balance(1)?.is_none());
+
+        bank.process()?;
+        let account = bank.account_balance(1)?.expect("client 1 should exist");
+        assert_eq!(account.total_funds(), rust_decimal_macros::dec!(3));
+        assert_eq!(account.held_funds(), rust_decimal::Decimal::ZERO);
+        assert!(!account.locked());
+        assert_eq!(
+            bank.account_balance(2)?
+                .map(|account| account.available_funds()),
+            Some(rust_decimal_macros::dec!(2))
+        );
+        assert!(bank.account_balance(3)?.is_none());
+
+        // the accounts are retained after writing them out
+        bank.accounts_balance_buffer()?;
+        assert!(bank.account_balance(1)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn parallel_jobs() -> anyhow::Result<()> {
+        for case in [
+            "deposit/ok",
+            "dispute/ok",
+            "dispute/report",
+            "withdrawal/ok",
+            "chargeback/ok",
+            "sorted_by_client/ok",
+        ] {
+            let input = std::path::Path::new("./test_data")
+                .join(case)
+                .join("input.csv");
+            let mut single = bank(input.clone())?;
+            let expected = single.ordered_accounts_balance_buffer()?;
+            for jobs in [2, 3, 8] {
+                let config = BankConfig {
+                    jobs: Some(jobs),
+                    ..Default::default()
+                };
+                let mut parallel = bank_with(input.clone(), LedgerConfig::default(), config)?;
+                assert_eq!(
+                    parallel.ordered_accounts_balance_buffer()?,
+                    expected,
+                    "{}",
+                    case
+                );
+                // partitions cannot tell a transaction of another client from an unknown one
+                let mut stats = single.stats();
+                stats.skipped_by_category = stats
+                    .skipped_by_category
+                    .into_iter()
+                    .map(|(category, skipped)| match category {
+                        "dispute_wrong_client" => ("dispute_unknown_transaction", skipped),
+                        "resolve_wrong_client" => ("resolve_unknown_transaction", skipped),
+                        "chargeback_wrong_client" => ("chargeback_unknown_transaction", skipped),
+                        category => (category, skipped),
+                    })
+                    .collect();
+                assert_eq!(parallel.stats(), stats, "{}", case);
+                assert_eq!(
+                    parallel.reconcile_global()?,
+                    single.reconcile_global()?,
+                    "{}",
+                    case
+                );
+            }
+        }
+
+        let config = BankConfig {
+            jobs: Some(2),
+            cross_client_disputes: true,
+            ..Default::default()
+        };
+        let input = "./test_data/dispute/ok/input.csv".into();
+        let error = bank_with(input, LedgerConfig::default(), config)?
+            .process()
+            .unwrap_err();
+        assert!(matches!(error, HeathError::InvalidConfig { .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn json_output() -> anyhow::Result<()> {
+        use crate::{config::OutputFormat, csv::account::AccountLog};
+        let test_folder = std::path::Path::new("./test_data/sorted_by_client/ok");
+        let config = BankConfig {
+            output_format: OutputFormat::Json,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        let json = bank.ordered_accounts_balance_buffer()?;
+        // the funds are strings, so no precision is lost to floats
+        assert!(json.contains(r#""held":"2.1234""#), "{}", json);
+
+        // round-trips into the same accounts, in the same order, as the csv output
+        let accounts = serde_json::from_str::<Vec<AccountLog>>(&json)?;
+        let mut w = csv::Writer::from_writer(vec![]);
+        for account in accounts {
+            w.serialize(account)?;
+        }
+        let csv = String::from_utf8(w.into_inner()?)?;
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            BankConfig::default(),
+        )?;
+        assert_eq!(csv, bank.ordered_accounts_balance_buffer()?);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_heavy_ledger() -> anyhow::Result<()> {
+        use crate::{
+            client::ClientId,
+            transaction::{TransactionLog, TransactionLogCommon},
+        };
+        use rust_decimal_macros::dec;
+
+        // 50k deposits, all of which are then disputed
+        let deposits = (0..50_000).map(|tx| TransactionLog::Deposit {
+            common: TransactionLogCommon::new(tx as ClientId % 100, tx),
+            amount: dec!(1),
+        });
+        let disputes = (0..50_000).map(|tx| TransactionLog::Dispute {
+            common: TransactionLogCommon::new(tx as ClientId % 100, tx),
+            percent: None,
+            amount: None,
+        });
+
+        // the bank's ledger is empty, so the disputed deposits can only be found in the index
+        let bank = Bank::from_transactions(deposits.chain(disputes), BankConfig::default())?;
+        assert_eq!(bank.index.len(), 50_000);
+
+        let held = bank
+            .ordered_accounts()?
+            .iter()
+            .map(|account| account.held_funds())
+            .sum::<rust_decimal::Decimal>();
+        assert_eq!(held, dec!(50000));
+        assert_eq!(bank.skipped.values().sum::<usize>(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn deadline() -> anyhow::Result<()> {
+        let input = tempfile::NamedTempFile::new()?;
+        let mut rows = String::from("type,client,tx,amount\n");
+        for tx in 0..5000 {
+            rows.push_str(&format!("deposit,{},{},1\n", tx, tx));
+        }
+        std::fs::write(input.path(), rows)?;
+
+        let config = BankConfig {
+            deadline: Some(std::time::Duration::ZERO),
+            ..Default::default()
+        };
+        let mut bank = bank_with(input.path().to_path_buf(), LedgerConfig::default(), config)?;
+        let error = bank.process().unwrap_err();
+        assert!(matches!(
+            error,
+            HeathError::Cancelled {
+                processed: 1000,
+                ..
+            }
+        ));
+
+        // the accounts computed before the deadline are still available
+        let output = bank.accounts_balance_buffer()?;
+        assert_eq!(output.lines().count(), 1 + 1000);
+        Ok(())
+    }
+
+    #[test]
+    fn deadline_parallel() -> anyhow::Result<()> {
+        let input = tempfile::NamedTempFile::new()?;
+        let mut rows = String::from("type,client,tx,amount\n");
+        for tx in 0..5000 {
+            rows.push_str(&format!("deposit,{},{},1\n", tx, tx));
+        }
+        std::fs::write(input.path(), rows)?;
+
+        let config = BankConfig {
+            deadline: Some(std::time::Duration::ZERO),
+            jobs: Some(2),
+            ..Default::default()
+        };
+        let mut bank = bank_with(input.path().to_path_buf(), LedgerConfig::default(), config)?;
+        let error = bank.process().unwrap_err();
+        assert!(matches!(
+            error,
+            HeathError::Cancelled {
+                processed: 1000,
+                ..
+            }
+        ));
+
+        // each partition stops at its own deadline check
+        let output = bank.accounts_balance_buffer()?;
+        assert_eq!(output.lines().count(), 1 + 2 * 1000);
+        Ok(())
+    }
+
+    #[test]
+    fn unique_clients() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/dispute/ok");
+        let config = BankConfig {
+            assert_unique_clients: true,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        bank.ordered_accounts_balance_buffer()?;
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_clients() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/dispute/ok");
+        let mut bank_a = bank(test_folder.join("input.csv"))?;
+        let mut bank_b = bank(test_folder.join("input.csv"))?;
+        bank_a.ordered_accounts_balance_buffer()?;
+        bank_b.ordered_accounts_balance_buffer()?;
+
+        // a broken merge which simply concatenates the accounts of both banks
+        let merged = bank_a
+            .accounts
+            .values()
+            .chain(bank_b.accounts.values())
+            .map(|a| a.to_csv())
+            .sorted_by(|a, b| a.client_id().cmp(&b.client_id()))
+            .collect::<Vec<_>>();
+
+        let error = super::assert_unique_clients(&merged).unwrap_err();
+        assert!(matches!(error, HeathError::DuplicateClient { client: 1 }));
+        Ok(())
+    }
+
+    #[test]
+    fn check_total() -> anyhow::Result<()> {
+        use crate::csv::account::AccountLog;
+        use rust_decimal_macros::dec;
+        let consistent = AccountLog::new(1, dec!(1.5), dec!(0.5), dec!(2), false);
+        super::check_total(&consistent)?;
+
+        let mismatched = AccountLog::new(2, dec!(1.5), dec!(0.5), dec!(2.0001), false);
+        let error = super::check_total(&mismatched).unwrap_err();
+        assert!(matches!(error, HeathError::TotalMismatch { client: 2, .. }));
+
+        // the accounts processed by the bank are consistent
+        let test_folder = std::path::Path::new("./test_data/deposit/ok");
+        let config = BankConfig {
+            check_invariants: true,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        bank.ordered_accounts_balance_buffer()?;
+
+        // as are the accounts rounded to a lower precision
+        let test_folder = std::path::Path::new("./test_data/precision/invariants");
+        let config = BankConfig {
+            check_invariants: true,
+            output_precision: Some(2),
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, LedgerConfig::default(), config)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn transaction_state() -> anyhow::Result<()> {
+        use crate::transaction::{DisputeOutcome, TransactionState};
+
+        let state = |applied, dispute| TransactionState { applied, dispute };
+
+        let mut resolved = bank("./test_data/resolve/ok/input.csv".into())?;
+        resolved.process()?;
+        assert_eq!(
+            resolved.transaction_state(1, 1)?,
+            state(true, DisputeOutcome::Resolved)
+        );
+        assert_eq!(
+            resolved.transaction_state(1, 3)?,
+            state(true, DisputeOutcome::Undisputed)
+        );
+        assert_eq!(
+            resolved.transaction_state(1, 2)?,
+            state(false, DisputeOutcome::Undisputed)
+        );
+
+        let mut charged_back = bank("./test_data/resolve/after_chargeback/input.csv".into())?;
+        charged_back.process()?;
+        assert_eq!(
+            charged_back.transaction_state(1, 1)?,
+            state(true, DisputeOutcome::Chargeback)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn orphan_accounts() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/dispute/orphans");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+        // the rejected withdrawal opened the account of client 4 without applying to it
+        assert_eq!(bank.orphan_accounts()?, vec![2, 3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn locked_clients() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/chargeback/locked_clients");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+        assert_eq!(bank.locked_clients()?, vec![2, 3]);
+        Ok(())
+    }
+
+    /// An in-memory `AccountStore` which records the maximum number of accounts it has held.
+    #[derive(Debug, Default)]
+    struct PeakStore {
+        accounts: HashMap<AccountId, Account>,
+        peak: usize,
+    }
+    impl AccountStore for PeakStore {
+        fn get(&self, account_id: AccountId) -> HeathResult<Option<Account>> {
+            AccountStore::get(&self.accounts, account_id)
+        }
+        fn insert(&mut self, account: Account) -> HeathResult<()> {
+            AccountStore::insert(&mut self.accounts, account)?;
+            self.peak = self.peak.max(self.accounts.len());
+            Ok(())
+        }
+        fn remove(&mut self, account_id: AccountId) -> HeathResult<Option<Account>> {
+            AccountStore::remove(&mut self.accounts, account_id)
+        }
+        fn iter(&self) -> Box<dyn Iterator<Item = HeathResult<Account>> + '_> {
+            AccountStore::iter(&self.accounts)
+        }
+        fn clear(&mut self) -> HeathResult<()> {
+            AccountStore::clear(&mut self.accounts)
+        }
+    }
+
+    #[test]
+    fn sorted_by_client() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/sorted_by_client/ok");
+        let (expected, buffered) = test(test_folder)?;
+        assert_eq!(expected, buffered);
+
+        let ledger = Ledger::from_path(test_folder.join("input.csv"), LedgerConfig::default())?;
+        let mut streaming = Bank::with_store(ledger, BankConfig::default(), PeakStore::default());
+        let mut w = csv::Writer::from_writer(vec![]);
+        streaming.process_sorted_by_client(None, |account| Ok(w.serialize(account)?))?;
+        let streamed = String::from_utf8(w.into_inner()?)?;
+        assert_eq!(buffered, streamed.trim());
+
+        // the same transactions, interleaved across clients, give the same accounts
+        let (_, unsorted) = test(&test_folder.with_file_name("unsorted"))?;
+        assert_eq!(unsorted, streamed.trim());
+
+        // only a single account was ever held, and it's dropped once emitted
+        assert_eq!(streaming.accounts.peak, 1);
+        assert!(streaming.accounts.accounts.is_empty());
+
+        let input = tempfile::NamedTempFile::new()?;
+        std::fs::write(
+            input.path(),
+            "type,client,tx,amount\ndeposit,2,1,1\ndeposit,1,2,1\n",
+        )?;
+        let mut bank = bank(input.path().to_path_buf())?;
+        let error = bank.process_sorted_by_client(None, |_| Ok(())).unwrap_err();
+        assert!(matches!(error, HeathError::NotSortedByClient { client: 1 }));
+
+        // the funds transferred to another client would be lost
+        std::fs::write(
+            input.path(),
+            "type,client,tx,amount,dest\ndeposit,1,1,10,\ntransfer,1,2,4,5\n",
+        )?;
+        let ledger = Ledger::from_path(input.path().to_path_buf(), LedgerConfig::default())?;
+        let mut bank = Bank::new(ledger, BankConfig::default());
+        let error = bank.process_sorted_by_client(None, |_| Ok(())).unwrap_err();
+        assert!(matches!(error, HeathError::InvalidConfig { .. }));
+
+        let config = BankConfig {
+            cross_client_disputes: true,
+            ..Default::default()
+        };
+        let mut bank = bank_with(input.path().to_path_buf(), LedgerConfig::default(), config)?;
+        let error = bank.process_sorted_by_client(None, |_| Ok(())).unwrap_err();
+        assert!(matches!(error, HeathError::InvalidConfig { .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn reconcile_global() -> anyhow::Result<()> {
+        use super::GlobalReconcile;
+        use rust_decimal_macros::dec;
+
+        let test_folder = std::path::Path::new("./test_data/dispute/report");
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+
+        let reconcile = bank.reconcile_global()?;
+        assert_eq!(reconcile.deposited, dec!(6.1234));
+        assert_eq!(reconcile.withdrawn, dec!(0));
+        // the charged back deposit of client 1
+        assert_eq!(reconcile.charged_back, dec!(1));
+        assert_eq!(reconcile.unaccounted, dec!(0));
+        assert_eq!(reconcile.expected(), dec!(5.1234));
+        assert_eq!(reconcile.actual, dec!(5.1234));
+        assert!(reconcile.matches());
+
+        // a deposit crediting more than its amount, eg: by an arithmetic bug, is not expected
+        let ledger = Ledger::from_str("type,client,tx,amount\ndeposit,1,1,1", Default::default())?;
+        let deposit = ledger.iter()?.filter_ok().next().unwrap();
+        let reconcile = GlobalReconcile {
+            actual: dec!(1.5),
+            ..GlobalReconcile::default()
+                .record(&deposit, dec!(1), dec!(1.5))
+                .unwrap()
+        };
+        assert_eq!(reconcile.deposited, dec!(1));
+        assert_eq!(reconcile.unaccounted, dec!(0.5));
+        assert_eq!(reconcile.expected(), dec!(1));
+        assert!(!reconcile.matches());
+        Ok(())
+    }
+
+    #[test]
+    fn stop_at_first_chargeback() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/chargeback/stop_at_first");
+        let config = BankConfig {
+            stop_at_first_chargeback: true,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        let output = bank.ordered_accounts_balance_buffer()?;
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(expected.trim(), output.trim());
+        assert_eq!(bank.skipped("after_first_chargeback"), 3);
+
+        // otherwise the locked account keeps processing transactions
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("1,1,5,6,true"), "{}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn process_stats() -> anyhow::Result<()> {
+        for case in [
+            "deposit/ok",
+            "dispute/ok",
+            "withdrawal/ok",
+            "chargeback/stop_at_first",
+        ] {
+            let input = std::path::Path::new("./test_data")
+                .join(case)
+                .join("input.csv");
+            let rows = std::fs::read_to_string(&input)?
+                .lines()
+                .skip(1)
+                .filter(|line| !line.trim().is_empty())
+                .count();
+            let stats = bank(input)?.process()?;
+            assert_eq!(stats.processed, rows, "{}", case);
+        }
+
+        let config = BankConfig {
+            stop_at_first_chargeback: true,
+            ..Default::default()
+        };
+        let input = std::path::Path::new("./test_data/chargeback/stop_at_first/input.csv");
+        let stats = bank_with(input.into(), LedgerConfig::default(), config)?.process()?;
+        assert_eq!(stats.skipped, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn progress() -> anyhow::Result<()> {
+        let input = std::path::Path::new("./test_data/dispute/ok/input.csv");
+        let mut reports = vec![];
+        let stats =
+            bank(input.into())?.process_with_progress(2, |progress| reports.push(progress))?;
+
+        let total_bytes = std::fs::metadata(input)?.len();
+        assert_eq!(reports.len(), stats.processed.div_ceil(2));
+        assert!(reports
+            .iter()
+            .tuple_windows()
+            .all(|(previous, next)| previous.bytes < next.bytes
+                && previous.processed < next.processed));
+        assert!(reports.iter().all(|p| p.total_bytes == Some(total_bytes)));
+        let last = reports.last().unwrap();
+        assert_eq!(last.processed, stats.processed);
+        assert_eq!(last.percent(), Some(100.0));
+        Ok(())
+    }
+
+    #[test]
+    fn output_precision() -> anyhow::Result<()> {
+        let input = std::path::Path::new("./test_data/deposit/ok/input.csv");
+        let output = |output_precision, fixed_decimals| -> anyhow::Result<String> {
+            let config = BankConfig {
+                output_precision,
+                fixed_decimals,
+                ..Default::default()
+            };
+            let mut bank = bank_with(input.into(), LedgerConfig::default(), config)?;
+            Ok(bank.ordered_accounts_balance_buffer()?)
+        };
+        assert!(output(None, false)?.contains("\n2,2,0,2,false"));
+        assert!(output(Some(4), false)?.contains("\n2,2,0,2,false"));
+        assert!(output(None, true)?.contains("\n2,2.0000,0.0000,2.0000,false"));
+        assert!(output(Some(1), true)?.contains("\n2,2.0,0.0,2.0,false"));
+        Ok(())
+    }
+
+    #[test]
+    fn from_transactions() -> anyhow::Result<()> {
+        use crate::{
+            transaction::{TransactionLog, TransactionLogCommon},
+            AccountInfo,
+        };
+        use rust_decimal_macros::dec;
+
+        let transactions = vec![
+            TransactionLog::Deposit {
+                common: TransactionLogCommon::new(1, 1),
+                amount: dec!(2.5),
+            },
+            TransactionLog::Deposit {
+                common: TransactionLogCommon::new(1, 2),
+                amount: dec!(1),
+            },
+            TransactionLog::Dispute {
+                common: TransactionLogCommon::new(1, 1),
+                percent: None,
+                amount: None,
+            },
+        ];
+        let bank = Bank::from_transactions(transactions, BankConfig::default())?;
+        let account = bank.account_balance(1)?.expect("client 1 has an account");
+        assert_eq!(account.available_funds(), dec!(1));
+        assert_eq!(account.held_funds(), dec!(2.5));
+        assert_eq!(account.total_funds(), dec!(3.5));
+        assert_eq!(bank.stats().processed, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn process_with() -> anyhow::Result<()> {
+        use crate::transactions::TransactionInfo;
+        use rust_decimal_macros::dec;
+        let test_folder = std::path::Path::new("./test_data/dispute/ok");
+        let rows = std::fs::read_to_string(test_folder.join("input.csv"))?
+            .lines()
+            .skip(1)
+            .count();
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        let mut journal = vec![];
+        bank.process_with(|transaction, account| {
+            assert_eq!(transaction.client_id(), account.client_id());
+            journal.push(account.total_funds());
+        })?;
+        assert_eq!(journal.len(), rows);
+        assert_eq!(journal, vec![dec!(1), dec!(2), dec!(3), dec!(3)]);
+
+        // the negative and malformed deposits are ignored before being executed, whereas the
+        // withdrawal without the funds is executed, leaving the account unchanged
+        let test_folder = std::path::Path::new("./test_data/hook/skipped");
+        let mut skipped = self::bank(test_folder.join("input.csv"))?;
+        let mut journal = vec![];
+        let stats = skipped.process_with(|transaction, account| {
+            journal.push((transaction.transaction_id(), account.total_funds()));
+        })?;
+        assert_eq!(journal, vec![(1, dec!(1)), (3, dec!(1)), (5, dec!(3))]);
+        assert_eq!(stats.skipped, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn sort_by_total_desc() -> anyhow::Result<()> {
+        use crate::config::SortBy;
+        let test_folder = std::path::Path::new("./test_data/sort_by/total_desc");
+        let config = BankConfig {
+            sort_by: SortBy::Total,
+            descending: true,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+
+        // by default the accounts are ordered by ascending client
+        let (_, actual) = test(test_folder)?;
+        let clients = actual.lines().skip(1).map(|line| &line[..1]).join(",");
+        assert_eq!(clients, "1,2,3");
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_resume() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/snapshot/ok");
+        let input = std::fs::read_to_string(test_folder.join("input.csv"))?;
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+
+        // the dispute of tx 2 is still held when the first half is snapshot
+        let half = input.lines().take(7).join("\n");
+        let ledger = Ledger::from_str(&half, LedgerConfig::default())?;
+        let mut first = Bank::new(ledger, BankConfig::default());
+        first.process()?;
+        let mut snapshot = vec![];
+        first.save_snapshot(&mut snapshot)?;
+
+        let mut resumed = bank(test_folder.join("input.csv"))?;
+        resumed.load_snapshot(snapshot.as_slice())?;
+        let stats = resumed.process()?;
+        assert_eq!(stats.processed, input.lines().count() - 1);
+        assert_eq!(resumed.ordered_accounts_balance_buffer()?, expected);
+
+        let mut single = bank(test_folder.join("input.csv"))?;
+        assert_eq!(single.ordered_accounts_balance_buffer()?, expected);
+        assert_eq!(resumed.reconcile_global()?, single.reconcile_global()?);
+        Ok(())
+    }
+
+    #[test]
+    fn process_stats_by_type() -> anyhow::Result<()> {
+        use crate::csv::transaction::TransactionType;
+        let stats = bank("./test_data/stats/ok/input.csv".into())?.process()?;
+        let processed = |kind| stats.processed_by_type.get(&kind).copied();
+        assert_eq!(processed(TransactionType::Deposit), Some(2));
+        assert_eq!(processed(TransactionType::Withdrawal), Some(2));
+        assert_eq!(processed(TransactionType::Dispute), Some(2));
+        assert_eq!(processed(TransactionType::Resolve), Some(1));
+        assert_eq!(processed(TransactionType::Chargeback), Some(1));
+        assert_eq!(stats.skipped, 3);
+        assert_eq!(
+            stats.skipped_by_category.iter().collect::<Vec<_>>(),
+            vec![
+                (&"chargeback_not_disputed", &1),
+                (&"dispute_unknown_transaction", &1),
+                (&"withdrawal_insufficient_funds", &1),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_timeline() -> anyhow::Result<()> {
+        use super::DisputeEvent;
+        use crate::transaction::DisputeOutcome;
+        use rust_decimal_macros::dec;
+        let mut bank = bank("./test_data/dispute/timeline/input.csv".into())?;
+        bank.process()?;
+
+        let event = |index, outcome, held| DisputeEvent {
+            index,
+            tx_id: 1,
+            outcome,
+            held,
+        };
+        assert_eq!(
+            bank.account_dispute_timeline(1),
+            vec![
+                event(2, DisputeOutcome::Disputed, dec!(10)),
+                event(3, DisputeOutcome::Resolved, dec!(0)),
+                event(5, DisputeOutcome::Disputed, dec!(10)),
+                event(6, DisputeOutcome::Chargeback, dec!(0)),
+            ]
+        );
+        assert!(bank.account_dispute_timeline(2).is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn process_stream() -> anyhow::Result<()> {
+        let test_folder = std::path::PathBuf::from("./test_data/dispute/ok");
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        let transactions = bank.ledger.iter()?.filter_ok().collect::<Vec<_>>();
+        let rows = transactions.len();
+
+        let stream = futures::stream::iter(transactions);
+        let stats = futures::executor::block_on(bank.process_stream(stream))?;
+        assert_eq!(stats.processed, rows);
+
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(bank.accounts_balance_buffer()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn batch_summaries() -> anyhow::Result<()> {
+        use rust_decimal_macros::dec;
+
+        let test_folder = std::path::Path::new("./test_data/batches/ok");
+        let ledger_config = LedgerConfig {
+            comments: true,
+            ..Default::default()
+        };
+        let config = BankConfig {
+            batch_summaries: true,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, ledger_config.clone(), config.clone())?;
+        assert_eq!(expected, actual);
+
+        let mut bank = bank_with(test_folder.join("input.csv"), ledger_config, config)?;
+        bank.process()?;
+        let summaries = bank
+            .batch_summaries()
+            .iter()
+            .map(|batch| (batch.label.as_str(), batch.rows, batch.net))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summaries,
+            vec![("2024-01-01", 3, dec!(13)), ("2024-01-02", 3, dec!(1))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn account_events() -> anyhow::Result<()> {
+        use crate::csv::event::AccountEventKind::*;
+        use rust_decimal_macros::dec;
+
+        let test_folder = std::path::Path::new("./test_data/events/ok");
+        let mut events = vec![];
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        bank.process_with_events(|event| events.push(event))?;
+
+        let events = events
+            .iter()
+            .map(|event| {
+                (
+                    event.client_id,
+                    event.tx_id,
+                    event.kind,
+                    event.delta,
+                    event.resulting_available,
+                    event.resulting_held,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                (1, 1, Credit, dec!(10), dec!(10), dec!(0)),
+                (1, 2, Credit, dec!(5), dec!(15), dec!(0)),
+                (1, 3, Debit, dec!(-3), dec!(12), dec!(0)),
+                (1, 2, Hold, dec!(5), dec!(7), dec!(5)),
+                (1, 2, Release, dec!(-5), dec!(12), dec!(0)),
+                (1, 2, Hold, dec!(5), dec!(7), dec!(5)),
+                (1, 2, Chargeback, dec!(-5), dec!(7), dec!(0)),
+                (2, 5, Credit, dec!(1), dec!(1), dec!(0)),
+            ]
+        );
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(expected.trim(), bank.accounts_balance_buffer()?.trim());
+        Ok(())
+    }
 }
@@ -1,2 +1,2 @@
 /// Type identifier for a client
-pub(crate) type ClientId = u16;
+pub type ClientId = u16;
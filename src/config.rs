@@ -0,0 +1,285 @@
+/// Bank processing and output configuration
+#[derive(Debug, Clone, Default)]
+pub struct BankConfig {
+    /// Fail if the output would contain more than one row for the same client.
+    pub assert_unique_clients: bool,
+    /// Fail if the output total of an account differs from its output available plus held funds,
+    /// eg: as they were rounded differently.
+    pub check_invariants: bool,
+    /// Stop processing if it takes longer than the deadline.
+    pub deadline: Option<std::time::Duration>,
+    /// Parse the ledger on a separate thread, queueing up to this many parsed transactions.
+    pub pipeline_capacity: Option<usize>,
+    /// Partition the transactions by client and process the partitions on this many threads.
+    pub jobs: Option<usize>,
+    /// Allow disputes, resolves and chargebacks to reference transactions of other clients.
+    /// The funds are held on, and released from, the account which owns the disputed transaction.
+    pub cross_client_disputes: bool,
+    /// Record a statement of the running available funds for this client.
+    pub statement_client: Option<crate::client::ClientId>,
+    /// Record an error log line for each ignored transaction.
+    pub error_log: bool,
+    /// Withdrawals exceeding the available funds drain them to zero rather than being rejected.
+    pub partial_withdrawals: bool,
+    /// Overdraft limit of each client, down to which its withdrawals may take the available
+    /// funds below zero. Clients without one have no overdraft.
+    pub overdraft_limits: std::collections::HashMap<crate::client::ClientId, rust_decimal::Decimal>,
+    /// A deposit with the (client, tx) of an earlier deposit adds its amount to the earlier one,
+    /// so that a dispute of the tx holds the combined amount.
+    pub merge_duplicate_deposits: bool,
+    /// What happens to a deposit or withdrawal reusing the tx id of an earlier one.
+    pub duplicate_policy: DuplicatePolicy,
+    /// Whether the tx ids are unique per client or across all clients.
+    pub duplicate_scope: DuplicateScope,
+    /// Output the highest total funds each account reached as an extra `peak_total` column.
+    pub with_peak: bool,
+    /// How the `locked` column is represented in the output.
+    pub bool_format: BoolFormat,
+    /// Decimal places the output amounts are rounded to, 4 unless set.
+    pub output_precision: Option<u32>,
+    /// Output the amounts with exactly the output precision decimal places, eg: `2.0000`, rather
+    /// than stripping their trailing zeros.
+    pub fixed_decimals: bool,
+    /// Format of the accounts balance output.
+    pub output_format: OutputFormat,
+    /// Omit the accounts which were only opened by a dispute, resolve or chargeback, and so have no
+    /// funds and are not locked, from the output.
+    pub omit_empty: bool,
+    /// The account field the output is ordered by, with ties ordered by client.
+    pub sort_by: SortBy,
+    /// Order the output descending rather than ascending.
+    pub descending: bool,
+    /// Withdrawals of clients without an account are skipped rather than opening an empty one.
+    pub no_phantom_on_withdrawal: bool,
+    /// Skip any disputes, resolves and chargebacks after this many have been applied.
+    pub max_disputes: Option<usize>,
+    /// A chargeback of an undisputed deposit implicitly disputes and then charges it back.
+    pub implicit_dispute_on_chargeback: bool,
+    /// Resolves and chargebacks whose disputed amount exceeds the held funds by no more than this,
+    /// eg: by a rounding crumb, release the held funds rather than being ignored.
+    pub held_epsilon: rust_decimal::Decimal,
+    /// When the account funds are rounded to 4 decimal places.
+    pub rounding: Rounding,
+    /// Which account fields are recorded by the transaction logs.
+    pub log_fields: LogFields,
+    /// Once an account is locked by a chargeback skip all of its later transactions.
+    pub stop_at_first_chargeback: bool,
+    /// A resolve is final, so that later disputes of the resolved transaction are ignored rather
+    /// than disputing it again.
+    pub no_redispute: bool,
+    /// How a dispute of an already disputed transaction affects its held funds.
+    pub repeated_dispute_policy: RepeatedDisputePolicy,
+    /// How a dispute of more than the available funds is handled.
+    pub dispute_policy: DisputePolicy,
+    /// Whether disputes, resolves and chargebacks apply to frozen accounts.
+    pub frozen_behavior: FrozenBehavior,
+    /// Whether deposits to frozen accounts are blocked or still credited.
+    pub frozen_deposit_policy: FrozenDepositPolicy,
+    /// Summarize each named batch of transactions, as marked by the ledger comments.
+    pub batch_summaries: bool,
+}
+
+/// How a dispute of an already disputed transaction affects its held funds
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RepeatedDisputePolicy {
+    /// Ignore the repeated dispute, keeping the prior hold.
+    #[default]
+    Ignore,
+    /// Hold the repeated dispute's amount on top of the prior hold, up to the transaction's
+    /// amount.
+    Stack,
+    /// Release the prior hold and hold the repeated dispute's amount instead.
+    Replace,
+}
+
+/// How a dispute of more than the available funds is handled
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DisputePolicy {
+    /// Ignore the dispute, as with a withdrawal of more than the available funds.
+    #[default]
+    SkipIfInsufficient,
+    /// Hold the whole disputed amount, pushing the available funds negative if need be.
+    AllowNegative,
+}
+
+/// Whether the dispute lifecycle transactions apply to a frozen, ie: locked, account
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FrozenBehavior {
+    /// Disputes, resolves and chargebacks still apply, only deposits, withdrawals and transfers
+    /// are blocked.
+    #[default]
+    AllowDisputes,
+    /// All transactions are blocked, including disputes, resolves and chargebacks.
+    BlockAll,
+}
+
+/// Whether a deposit to a frozen, ie: locked, account is credited
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FrozenDepositPolicy {
+    /// Ignore the deposit, as with any other transaction moving funds in or out of the account.
+    #[default]
+    Block,
+    /// Credit the deposit to the available funds, only withdrawals and transfers are blocked.
+    Allow,
+}
+
+/// What happens to a deposit or withdrawal reusing the tx id of an earlier one
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Apply the duplicate, logging a warning, though disputes only reference the first one.
+    #[default]
+    Warn,
+    /// Skip the duplicate, keeping only the first transaction with the tx id.
+    Ignore,
+}
+
+/// Scope within which the tx ids must be unique
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DuplicateScope {
+    /// The same tx id may be reused by different clients.
+    #[default]
+    Client,
+    /// The tx ids are unique across all clients.
+    Global,
+}
+
+/// Format of the accounts balance output
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Csv with the client, available, held, total and locked columns.
+    #[default]
+    Csv,
+    /// A JSON array of the accounts, with the funds as strings to preserve their precision.
+    Json,
+    /// An aligned ASCII table of the accounts, for terminals.
+    Table,
+}
+
+/// The account field the output is ordered by
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SortBy {
+    /// The client id.
+    #[default]
+    Client,
+    /// The available funds.
+    Available,
+    /// The held funds.
+    Held,
+    /// The total funds.
+    Total,
+}
+
+/// How boolean output columns are represented
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BoolFormat {
+    /// `true` or `false`.
+    #[default]
+    TrueFalse,
+    /// `1` or `0`.
+    OneZero,
+    /// `Y` or `N`.
+    YesNo,
+}
+
+/// When the account funds are rounded to 4 decimal places
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Rounding {
+    /// Round on every account operation.
+    #[default]
+    PerOperation,
+    /// Keep the full precision internally and round only on the output, minimizing the
+    /// cumulative rounding error.
+    OutputOnly,
+    /// Never round nor normalize, preserving whatever precision the input carried.
+    Never,
+}
+
+impl Rounding {
+    /// Round the amount as part of an account operation.
+    pub(crate) fn operation(&self, amount: rust_decimal::Decimal) -> rust_decimal::Decimal {
+        match self {
+            Self::PerOperation => amount.round_dp(4),
+            Self::OutputOnly | Self::Never => amount,
+        }
+    }
+}
+
+/// Which account fields are recorded by the transaction logs
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LogFields {
+    /// Only the client id and the funds.
+    Minimal,
+    /// The whole account, including its disputes.
+    #[default]
+    Full,
+}
+
+/// Ledger input parsing configuration
+#[derive(Debug, Clone, Default)]
+pub struct LedgerConfig {
+    /// Input amounts are given in minor units and are divided by 10^amount_scale when parsed.
+    pub amount_scale: u32,
+    /// Maps input transaction type labels into the canonical type labels, eg: credit => deposit.
+    pub type_map: std::collections::HashMap<String, String>,
+    /// Maps input column names into the canonical column names, eg: customer => client.
+    pub header_map: std::collections::HashMap<String, String>,
+    /// Parse amounts given as `a/b` ratios, eg: 3/4 => 0.75, rounded to 4 decimal places.
+    pub allow_ratio_amounts: bool,
+    /// Delimiter of the csv fields, defaults to a comma.
+    pub delimiter: Option<u8>,
+    /// Decimal separator of the input amounts, eg: a comma for 1,50, defaults to a dot.
+    pub decimal_separator: Option<char>,
+    /// Format of the ledger file.
+    pub format: InputFormat,
+    /// Csv lines starting with `#` are comments, with `# batch: <label>` comments marking the
+    /// start of a named batch of transactions.
+    pub comments: bool,
+    /// Transactions of the clients within this range, eg: test clients, are not processed.
+    pub exclude_clients: Option<std::ops::RangeInclusive<crate::client::ClientId>>,
+    /// How to handle amounts with more than 4 decimal places.
+    pub amount_precision: AmountPrecision,
+}
+
+/// Handling of input amounts with more than the supported 4 decimal places
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AmountPrecision {
+    /// Accept the amount, which is then rounded as configured by the `Rounding`.
+    #[default]
+    Round,
+    /// Skip the transaction, with a warning, rather than mangling its amount.
+    Reject,
+}
+
+impl AmountPrecision {
+    /// Maximum number of decimal places of the input amounts.
+    pub const MAX_SCALE: u32 = 4;
+}
+
+/// Column by which the ledger rows may be required to be sorted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Client,
+    Tx,
+}
+
+impl SortKey {
+    /// Name of the sorted column.
+    pub fn column(&self) -> &'static str {
+        match self {
+            Self::Client => "client",
+            Self::Tx => "tx",
+        }
+    }
+}
+
+/// Format of the ledger file
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum InputFormat {
+    /// Csv with the type, client, tx and amount columns.
+    #[default]
+    Csv,
+    /// A stream of JSON transaction objects, tagged by their type, eg:
+    /// {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+    /// {"type": "dispute", "client": 1, "tx": 1}
+    Json,
+}
@@ -1,8 +1,13 @@
-use crate::{account::AccountInfo, client::ClientId};
-use serde::{Deserialize, Serialize};
+use crate::{account::AccountInfo, client::ClientId, config::BoolFormat, error::HeathResult};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fs::File,
+    io::{Seek, SeekFrom},
+    path::Path,
+};
 
 #[derive(Debug, Default, Serialize, Deserialize)]
-pub(crate) struct AccountLog {
+pub struct AccountLog {
     /// Client identifier.
     #[serde(rename = "client")]
     client_id: ClientId,
@@ -19,7 +24,14 @@ pub(crate) struct AccountLog {
     total_funds: rust_decimal::Decimal,
     /// Whether the account is locked. An account is locked if a charge back occur.
     #[serde(rename = "locked")]
-    locked: bool,
+    locked: OutputBool,
+    /// The highest total funds the account reached, only output when requested.
+    #[serde(
+        rename = "peak_total",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    peak_total: Option<rust_decimal::Decimal>,
 }
 impl AccountLog {
     pub(crate) fn new(
@@ -34,11 +46,90 @@ impl AccountLog {
             available_funds,
             held_funds,
             total_funds,
-            locked,
+            locked: OutputBool {
+                value: locked,
+                format: BoolFormat::default(),
+            },
+            peak_total: None,
+        }
+    }
+    /// Record the peak total funds as an extra output column.
+    pub(crate) fn with_peak_total(mut self, peak_total: rust_decimal::Decimal) -> Self {
+        self.peak_total = Some(peak_total);
+        self
+    }
+    /// Round the amounts to the given decimal places, either stripping the trailing zeros or,
+    /// if `fixed`, padding them to exactly that many decimal places, eg: `2.0000`.
+    /// The total is the sum of the rounded available and held funds, rather than rounded on its
+    /// own, so that the columns still add up.
+    pub(crate) fn with_precision(mut self, decimals: u32, fixed: bool) -> Self {
+        let format = |amount: rust_decimal::Decimal| {
+            let mut amount = amount.round_dp(decimals);
+            if fixed {
+                amount.rescale(decimals);
+                amount
+            } else {
+                amount.normalize()
+            }
+        };
+        self.available_funds = format(self.available_funds);
+        self.held_funds = format(self.held_funds);
+        self.total_funds = format(self.available_funds + self.held_funds);
+        self.peak_total = self.peak_total.map(format);
+        self
+    }
+    /// Represent the `locked` column with the given format.
+    pub(crate) fn with_bool_format(mut self, format: BoolFormat) -> Self {
+        self.locked.format = format;
+        self
+    }
+}
+
+/// A boolean column which is serialized as per its `BoolFormat`.
+/// Any of the formats are accepted when deserializing.
+#[derive(Debug, Default, Clone, Copy)]
+struct OutputBool {
+    value: bool,
+    format: BoolFormat,
+}
+
+impl Serialize for OutputBool {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match (self.format, self.value) {
+            (BoolFormat::TrueFalse, value) => serializer.serialize_bool(value),
+            (BoolFormat::OneZero, value) => serializer.serialize_u8(value as u8),
+            (BoolFormat::YesNo, true) => serializer.serialize_str("Y"),
+            (BoolFormat::YesNo, false) => serializer.serialize_str("N"),
         }
     }
 }
 
+impl<'de> Deserialize<'de> for OutputBool {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        /// The representations of the formats, eg: a JSON bool or a csv field.
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Bool(bool),
+            Number(u8),
+            Text(String),
+        }
+        let (value, format) = match Repr::deserialize(deserializer)? {
+            Repr::Bool(value) => (value, BoolFormat::TrueFalse),
+            Repr::Number(1) => (true, BoolFormat::OneZero),
+            Repr::Number(0) => (false, BoolFormat::OneZero),
+            Repr::Text(text) if text == "Y" => (true, BoolFormat::YesNo),
+            Repr::Text(text) if text == "N" => (false, BoolFormat::YesNo),
+            _ => {
+                return Err(serde::de::Error::custom(
+                    "expected true, false, 1, 0, Y or N",
+                ))
+            }
+        };
+        Ok(Self { value, format })
+    }
+}
+
 impl AccountInfo for AccountLog {
     fn client_id(&self) -> ClientId {
         self.client_id
@@ -53,13 +144,48 @@ impl AccountInfo for AccountLog {
         self.total_funds
     }
     fn locked(&self) -> bool {
-        self.locked
+        self.locked.value
     }
 }
 
+/// Open the accounts csv output at the given path to resume writing it, eg: after a crash.
+/// Any partially written last row is dropped and the headers are written only if missing.
+/// Returns the writer, positioned after the complete rows, and the last client written.
+pub fn resume_output(path: &Path) -> HeathResult<(csv::Writer<File>, Option<ClientId>)> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(error) => return Err(error.into()),
+    };
+    let complete = content
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let last_client = csv::Reader::from_reader(&content[..complete])
+        .deserialize::<AccountLog>()
+        .last()
+        .transpose()?
+        .map(|account| account.client_id);
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    file.set_len(complete as u64)?;
+    file.seek(SeekFrom::End(0))?;
+    let writer = csv::WriterBuilder::new()
+        .has_headers(complete == 0)
+        .from_writer(file);
+    Ok((writer, last_client))
+}
+
 #[cfg(test)]
+#[allow(clippy::unused_enumerate_index)]
 pub(crate) mod tests {
     use super::AccountLog;
+    use crate::{account::AccountInfo, config::BoolFormat};
+    use rust_decimal_macros::dec;
 
     #[test]
     /// Basic CSV test, read some test input and write it back, it should be the same
@@ -110,4 +236,94 @@ client, available, held, total, locked
         assert_eq!(test_input.replace(' ', ""), output);
         Ok(())
     }
+
+    #[test]
+    fn resume_output() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/sorted_by_client/ok");
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().join("output.csv");
+
+        // crashed while writing the second client's row
+        let truncated = expected.find("2,0.5").unwrap() + 4;
+        std::fs::write(&output, &expected[..truncated])?;
+        let (mut w, resume_after) = super::resume_output(&output)?;
+        assert_eq!(resume_after, Some(1));
+        let mut bank = crate::bank::tests::bank(test_folder.join("input.csv"))?;
+        bank.process_sorted_by_client(resume_after, |account| Ok(w.serialize(account)?))?;
+        w.flush()?;
+        drop(w);
+        assert_eq!(std::fs::read_to_string(&output)?, expected);
+
+        // nothing written yet
+        std::fs::remove_file(&output)?;
+        let (mut w, resume_after) = super::resume_output(&output)?;
+        assert_eq!(resume_after, None);
+        bank.process_sorted_by_client(resume_after, |account| Ok(w.serialize(account)?))?;
+        w.flush()?;
+        drop(w);
+        assert_eq!(std::fs::read_to_string(&output)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn bool_format() -> anyhow::Result<()> {
+        let cases = [
+            (BoolFormat::TrueFalse, "true", "false"),
+            (BoolFormat::OneZero, "1", "0"),
+            (BoolFormat::YesNo, "Y", "N"),
+        ];
+        for (format, locked, unlocked) in cases {
+            let mut w = csv::Writer::from_writer(vec![]);
+            w.serialize(
+                AccountLog::new(1, dec!(0), dec!(0), dec!(0), true).with_bool_format(format),
+            )?;
+            w.serialize(
+                AccountLog::new(2, dec!(1), dec!(0), dec!(1), false).with_bool_format(format),
+            )?;
+            let output = String::from_utf8(w.into_inner()?)?;
+            let expected = format!(
+                "client,available,held,total,locked\n1,0,0,0,{}\n2,1,0,1,{}\n",
+                locked, unlocked
+            );
+            assert_eq!(output, expected);
+
+            // and any representation is read back
+            let mut reader = csv::Reader::from_reader(output.as_bytes());
+            let locks = reader
+                .deserialize::<AccountLog>()
+                .map(|account| account.map(|account| account.locked.value))
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(locks, vec![true, false]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn precision() -> anyhow::Result<()> {
+        let cases = [
+            (4, false, "2,0.1235,2.1235"),
+            (4, true, "2.0000,0.1235,2.1235"),
+            (2, false, "2,0.12,2.12"),
+            (2, true, "2.00,0.12,2.12"),
+        ];
+        for (decimals, fixed, amounts) in cases {
+            let mut w = csv::Writer::from_writer(vec![]);
+            w.serialize(
+                AccountLog::new(1, dec!(2), dec!(0.12346), dec!(2.12346), false)
+                    .with_precision(decimals, fixed),
+            )?;
+            let output = String::from_utf8(w.into_inner()?)?;
+            let expected = format!("client,available,held,total,locked\n1,{},false\n", amounts);
+            assert_eq!(output, expected);
+        }
+
+        // the total adds up the rounded funds, rather than rounding 0.03 on its own
+        let account = AccountLog::new(1, dec!(0.015), dec!(0.015), dec!(0.03), false)
+            .with_precision(2, false);
+        assert_eq!(account.available_funds(), dec!(0.02));
+        assert_eq!(account.held_funds(), dec!(0.02));
+        assert_eq!(account.total_funds(), dec!(0.04));
+        Ok(())
+    }
 }
@@ -0,0 +1,27 @@
+use crate::{client::ClientId, csv::transaction::TransactionId};
+use serde::{Deserialize, Serialize};
+
+/// A dispute report entry.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct DisputeLog {
+    /// Client identifier.
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    /// The disputed transaction.
+    #[serde(rename = "tx")]
+    pub tx_id: TransactionId,
+    /// The current state of the dispute.
+    pub state: DisputeLogState,
+    /// The amount of the disputed transaction, if it's still in the ledger.
+    pub original_amount: Option<rust_decimal::Decimal>,
+    /// The amount which is currently held by the dispute.
+    pub held_now: rust_decimal::Decimal,
+}
+
+/// The state of a reported dispute.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DisputeLogState {
+    Disputed,
+    Chargeback,
+}
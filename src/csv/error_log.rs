@@ -0,0 +1,15 @@
+use crate::{client::ClientId, csv::transaction::TransactionId};
+use serde::Serialize;
+
+/// An error log line, recorded for each transaction ignored by the bank.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ErrorLog {
+    /// Line of the transaction in the ledger.
+    pub line: u64,
+    /// Client ID, unless the row is malformed.
+    pub client: Option<ClientId>,
+    /// Transaction ID, unless the row is malformed.
+    pub tx: Option<TransactionId>,
+    /// Category of the reason why the transaction was ignored, eg: `withdrawal_insufficient_funds`.
+    pub reason: &'static str,
+}
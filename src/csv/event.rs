@@ -0,0 +1,40 @@
+use crate::{client::ClientId, csv::transaction::TransactionId};
+use serde::{Deserialize, Serialize};
+
+/// A change of an account's funds, emitted as each transaction is applied.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AccountEvent {
+    /// Client identifier of the account whose funds changed.
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    /// ID of the transaction which changed the funds.
+    #[serde(rename = "tx")]
+    pub tx_id: TransactionId,
+    /// Kind of change.
+    pub kind: AccountEventKind,
+    /// Funds moved: the change of the total funds or, for holds and releases which keep the total
+    /// funds, the change of the held funds.
+    pub delta: rust_decimal::Decimal,
+    /// The available funds after the change.
+    pub resulting_available: rust_decimal::Decimal,
+    /// The held funds after the change.
+    pub resulting_held: rust_decimal::Decimal,
+}
+
+/// Kind of change of an account's funds
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountEventKind {
+    /// Funds credited by a deposit.
+    Credit,
+    /// Funds debited by a withdrawal.
+    Debit,
+    /// Funds held by a dispute.
+    Hold,
+    /// Held funds released by a resolve.
+    Release,
+    /// Held funds removed by a chargeback.
+    Chargeback,
+    /// Funds added, or removed, by an adjustment.
+    Adjust,
+}
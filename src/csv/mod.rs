@@ -1,2 +1,6 @@
 pub(crate) mod account;
+pub(crate) mod dispute;
+pub(crate) mod error_log;
+pub(crate) mod event;
+pub(crate) mod statement;
 pub(crate) mod transaction;
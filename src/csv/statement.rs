@@ -0,0 +1,17 @@
+use crate::csv::transaction::{TransactionId, TransactionType};
+use serde::{Deserialize, Serialize};
+
+/// A bank statement line, recorded after a client's transaction is applied.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct StatementLog {
+    /// Transaction ID.
+    #[serde(rename = "tx")]
+    pub tx_id: TransactionId,
+    /// Transaction Type.
+    #[serde(rename = "type")]
+    pub type_: TransactionType,
+    /// Transaction amount, if the transaction carries one.
+    pub amount: Option<rust_decimal::Decimal>,
+    /// The client's available funds after the transaction was applied.
+    pub available_after: rust_decimal::Decimal,
+}
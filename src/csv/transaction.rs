@@ -2,14 +2,14 @@ use crate::{client::ClientId, transactions::TransactionInfo};
 use serde::{Deserialize, Serialize};
 
 /// Type identifier for a transaction
-pub(crate) type TransactionId = u32;
+pub type TransactionId = u32;
 
 /// The input will be a CSV file with the columns type, client, tx, and amount. You can assume the
 /// type is a string, the client column is a valid u16 client ID, the tx is a valid u32 transaction
 /// ID, and the amount is a rust_decimal::Decimal value with a precision of up to four places past
 /// the rust_decimal::Decimal.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) struct TransactionLogCsv {
+pub struct TransactionLogCsv {
     /// Transaction Type.
     #[serde(rename = "type")]
     type_: TransactionType,
@@ -17,11 +17,19 @@ pub(crate) struct TransactionLogCsv {
     #[serde(rename = "client")]
     client_id: ClientId,
     /// Transaction ID.
+    /// Adjustments, unlocks, freezes and unfreezes are not referenced by other transactions and so
+    /// may leave it empty.
     #[serde(rename = "tx")]
-    tx_id: TransactionId,
+    tx_id: Option<TransactionId>,
     /// Transaction amount with a precision of up to four places past the rust_decimal::Decimal.
     #[serde(rename = "amount")]
     amount: Option<rust_decimal::Decimal>,
+    /// Percentage of the disputed transaction amount, only given by partial disputes.
+    #[serde(rename = "percent", default, skip_serializing)]
+    percent: Option<rust_decimal::Decimal>,
+    /// Client ID of the destination account, only given by transfers.
+    #[serde(rename = "dest", default, skip_serializing)]
+    dest: Option<ClientId>,
 }
 
 impl TransactionInfo for TransactionLogCsv {
@@ -32,30 +40,119 @@ impl TransactionInfo for TransactionLogCsv {
         self.client_id
     }
     fn transaction_id(&self) -> TransactionId {
-        self.tx_id
+        self.tx_id.unwrap_or_default()
     }
     fn amount(&self) -> Option<rust_decimal::Decimal> {
         self.amount
     }
 }
 impl TransactionLogCsv {
+    pub(crate) fn new(
+        type_: TransactionType,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: Option<rust_decimal::Decimal>,
+    ) -> Self {
+        Self {
+            type_,
+            client_id,
+            tx_id: Some(tx_id),
+            amount,
+            percent: None,
+            dest: None,
+        }
+    }
+    /// Validate that the columns required by the transaction type are present.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match self.type_ {
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Adjust
+            | TransactionType::Transfer
+                if self.amount.is_none() =>
+            {
+                Err(format!("{:?} is missing the amount", self.type_))
+            }
+            TransactionType::Adjust
+            | TransactionType::Unlock
+            | TransactionType::Freeze
+            | TransactionType::Unfreeze => Ok(()),
+            TransactionType::Transfer if self.dest.is_none() => {
+                Err("Transfer is missing the dest".to_string())
+            }
+            _ if self.dest.is_some() && self.type_ != TransactionType::Transfer => {
+                Err(format!("{:?} cannot have a dest", self.type_))
+            }
+            _ if self.percent.is_some() && self.type_ != TransactionType::Dispute => {
+                Err(format!("{:?} cannot have a percent", self.type_))
+            }
+            _ if self.percent.is_some_and(|percent| !valid_percent(percent)) => {
+                Err("Dispute percent must be within ]0, 100]".to_string())
+            }
+            TransactionType::Dispute if self.percent.is_some() && self.amount.is_some() => {
+                Err("Dispute cannot have both a percent and an amount".to_string())
+            }
+            TransactionType::Dispute if self.amount.is_some_and(|amount| amount <= 0.into()) => {
+                Err("Dispute amount must be positive".to_string())
+            }
+            _ if self.tx_id.is_none() => Err(format!("{:?} is missing the tx", self.type_)),
+            _ => Ok(()),
+        }
+    }
+    /// The percentage of the disputed transaction amount, if one was given.
+    pub(crate) fn percent(&self) -> Option<rust_decimal::Decimal> {
+        self.percent
+    }
+    /// The destination client ID, if one was given.
+    pub(crate) fn dest(&self) -> Option<ClientId> {
+        self.dest
+    }
+    /// The transaction ID, if one was given.
+    pub(crate) fn optional_transaction_id(&self) -> Option<TransactionId> {
+        self.tx_id
+    }
     #[allow(dead_code)]
     pub(crate) fn log_info(&self) {
         tracing::info!(type_=?self.transaction_type(), client=self.client_id(), tx=%self.transaction_id(), amount=?self.amount());
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Whether the dispute percent is within ]0, 100].
+pub(crate) fn valid_percent(percent: rust_decimal::Decimal) -> bool {
+    percent > rust_decimal::Decimal::ZERO && percent <= rust_decimal::Decimal::ONE_HUNDRED
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
-pub(crate) enum TransactionType {
+pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    Adjust,
+    Unlock,
+    Transfer,
+    Freeze,
+    Unfreeze,
+}
+impl TransactionType {
+    /// Whether this is a dispute lifecycle transaction, ie: dispute, resolve or chargeback.
+    pub(crate) fn is_dispute(&self) -> bool {
+        matches!(self, Self::Dispute | Self::Resolve | Self::Chargeback)
+    }
+}
+impl std::str::FromStr for TransactionType {
+    type Err = serde::de::value::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use serde::de::IntoDeserializer;
+        Self::deserialize(s.into_deserializer())
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::unused_enumerate_index, clippy::map_flatten)]
 mod tests {
     use super::TransactionLogCsv;
     #[test]
@@ -68,6 +165,7 @@ deposit,2,2,2
 deposit,1,3,2
 withdrawal,1,4,1.5
 withdrawal,2,5,3
+adjust,2,,-0.5
 ";
         let mut test_reader = csv::Reader::from_reader(test_input.as_bytes());
         let transactions = test_reader
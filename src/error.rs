@@ -0,0 +1,57 @@
+use crate::client::ClientId;
+use std::time::Duration;
+
+/// A heath error.
+/// Returned by the `Bank` and `Ledger` APIs so that callers may match on the kind of failure.
+#[derive(thiserror::Error, Debug)]
+pub enum HeathError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse line {line}: {message}")]
+    Parse { line: u64, message: String },
+    #[error("Invalid transaction on line {line}: {reason}")]
+    InvalidTransaction { line: u64, reason: String },
+    #[error("The first {rows} rows of the ledger differ from the previously processed rows")]
+    HistoryAltered { rows: usize },
+    #[error("Invalid ledger configuration: {reason}")]
+    InvalidConfig { reason: String },
+    #[error("Arithmetic overflow when computing the {context}")]
+    Overflow { context: &'static str },
+    #[error(
+        "Cancelled after processing {processed} transactions, deadline of {deadline:?} exceeded"
+    )]
+    Cancelled {
+        deadline: Duration,
+        processed: usize,
+    },
+    #[error("Output contains duplicate rows for client {client}")]
+    DuplicateClient { client: ClientId },
+    #[error("Output total {total} of client {client} differs from its available {available} plus held {held}")]
+    TotalMismatch {
+        client: ClientId,
+        available: rust_decimal::Decimal,
+        held: rust_decimal::Decimal,
+        total: rust_decimal::Decimal,
+    },
+    #[error("Input is not sorted by client, client {client} is out of order")]
+    NotSortedByClient { client: ClientId },
+    #[error("Input is not sorted by {column}, row {row} is out of order")]
+    NotSorted { column: &'static str, row: usize },
+    #[error("Value '{value}' does not fit within a field of width {width}")]
+    FieldOverflow { value: String, width: usize },
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Output is not valid UTF-8: {0}")]
+    Utf8(#[from] std::string::FromUtf8Error),
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "sled")]
+    #[error("Account store error: {0}")]
+    Sled(#[from] sled::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Result type of the heath APIs.
+pub type HeathResult<T> = Result<T, HeathError>;
@@ -0,0 +1,170 @@
+use crate::{
+    account::AccountInfo,
+    csv::account::AccountLog,
+    error::{HeathError, HeathResult},
+};
+use serde::Deserialize;
+
+/// A fixed-width record layout, eg:
+/// [[field]]
+/// column = "client"
+/// width = 10
+/// align = "right"
+///
+/// [[field]]
+/// column = "available"
+/// width = 18
+/// implied_decimals = 4
+#[derive(Debug, Clone, Deserialize)]
+pub struct Layout {
+    #[serde(rename = "field")]
+    fields: Vec<LayoutField>,
+}
+
+/// A field of the fixed-width record
+#[derive(Debug, Clone, Deserialize)]
+struct LayoutField {
+    /// The account column written in this field.
+    column: Column,
+    /// Width of the field, in characters.
+    width: usize,
+    /// Alignment of the value within the field.
+    #[serde(default)]
+    align: Align,
+    /// Character used to pad the value up to the width.
+    #[serde(default = "LayoutField::default_pad")]
+    pad: char,
+    /// Amounts are written as integers with this many implied decimal places, eg: with 4 implied
+    /// decimals 1.5 is written as 15000.
+    implied_decimals: Option<u32>,
+}
+impl LayoutField {
+    fn default_pad() -> char {
+        ' '
+    }
+}
+
+/// The account columns, with the locked column written as Y or N
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Column {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+}
+
+/// Alignment of a value within its field
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Align {
+    #[default]
+    Left,
+    Right,
+}
+
+impl Layout {
+    /// Get the fixed-width record of the account.
+    /// Fails with `HeathError::FieldOverflow` if a value does not fit within its field.
+    pub fn record(&self, account: &AccountLog) -> HeathResult<String> {
+        let mut record = String::new();
+        for field in &self.fields {
+            let amount = |amount: rust_decimal::Decimal| match field.implied_decimals {
+                None => amount.to_string(),
+                Some(decimals) => {
+                    let mut amount = amount.round_dp(decimals);
+                    amount.rescale(decimals);
+                    amount.mantissa().to_string()
+                }
+            };
+            let value = match field.column {
+                Column::Client => account.client_id().to_string(),
+                Column::Available => amount(account.available_funds()),
+                Column::Held => amount(account.held_funds()),
+                Column::Total => amount(account.total_funds()),
+                Column::Locked => if account.locked() { "Y" } else { "N" }.to_string(),
+            };
+            let length = value.chars().count();
+            if length > field.width {
+                return Err(HeathError::FieldOverflow {
+                    value,
+                    width: field.width,
+                });
+            }
+            let padding = std::iter::repeat_n(field.pad, field.width - length);
+            match field.align {
+                Align::Left => record.extend(value.chars().chain(padding)),
+                // zeros pad the digits of a negative value, eg: -000015000 rather than 0000-15000
+                Align::Right if field.pad == '0' && value.starts_with('-') => {
+                    record.push('-');
+                    record.extend(padding.chain(value.chars().skip(1)));
+                }
+                Align::Right => record.extend(padding.chain(value.chars())),
+            }
+        }
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Layout;
+    use crate::{
+        bank::tests::bank_with,
+        config::{BankConfig, DisputePolicy, LedgerConfig},
+    };
+
+    /// Get the fixed-width records of the processed test folder, as per the `ok` layout, along
+    /// with the expected records.
+    fn records(
+        test_folder: &std::path::Path,
+        config: BankConfig,
+    ) -> anyhow::Result<(Vec<String>, Vec<String>)> {
+        let layout = std::fs::read_to_string("./test_data/fixed_width/ok/layout.toml")?;
+        let layout: Layout = toml::from_str(&layout)?;
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        bank.process()?;
+
+        let records = bank
+            .ordered_accounts()?
+            .iter()
+            .map(|account| layout.record(account))
+            .collect::<Result<Vec<_>, _>>()?;
+        let expected = std::fs::read_to_string(test_folder.join("output.txt"))?;
+        Ok((records, expected.lines().map(String::from).collect()))
+    }
+
+    #[test]
+    fn fixed_width() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/fixed_width/ok");
+        let (records, expected) = records(test_folder, BankConfig::default())?;
+        assert_eq!(records, expected);
+
+        let record = &records[1];
+        assert_eq!(record.len(), 10 + 18 * 3 + 1);
+        assert_eq!(&record[0..10], "         2");
+        assert_eq!(&record[10..28], "000000000000015000");
+        assert_eq!(&record[28..46], "000000000000012346");
+        assert_eq!(&record[46..64], "000000000000027346");
+        assert_eq!(&record[64..], "N");
+        Ok(())
+    }
+    #[test]
+    fn negative() -> anyhow::Result<()> {
+        // the dispute of the deposit takes the available funds negative
+        let test_folder = std::path::Path::new("./test_data/fixed_width/negative");
+        let config = BankConfig {
+            dispute_policy: DisputePolicy::AllowNegative,
+            ..Default::default()
+        };
+        let (records, expected) = records(test_folder, config)?;
+        assert_eq!(records, expected);
+        assert_eq!(&records[0][10..28], "-00000000000085000");
+        Ok(())
+    }
+}
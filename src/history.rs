@@ -0,0 +1,176 @@
+use crate::{
+    bank::{Bank, BankSnapshot},
+    error::{HeathError, HeathResult},
+    manifest::hex,
+    store::AccountStore,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+/// The history of an append-only ledger: a rolling hash of the rows processed so far, used to
+/// detect whether they were edited before the ledger is processed again, along with the state of
+/// the bank once they were processed, so that only the rows appended since are processed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct History {
+    /// Number of rows processed, including any headers.
+    pub rows: usize,
+    /// Hex encoded SHA-256 of the rows processed, each terminated by a newline.
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    snapshot: Option<BankSnapshot>,
+}
+
+impl History {
+    /// Get the history of all the rows of the ledger at the given path.
+    pub fn of(ledger: &Path) -> HeathResult<Self> {
+        let (rows, hasher) = Self::hash(ledger, None)?;
+        Ok(Self {
+            rows,
+            sha256: hex(&hasher.finalize()),
+            snapshot: None,
+        })
+    }
+    /// Record the state of the bank which processed the rows of this history.
+    pub fn with_bank<S: AccountStore>(mut self, bank: &Bank<S>) -> HeathResult<Self> {
+        self.snapshot = Some(bank.snapshot()?);
+        Ok(self)
+    }
+    /// Resume the bank from the state recorded by this history, if any, so that it skips the
+    /// rows which were already processed.
+    pub fn resume<S: AccountStore>(&self, bank: &mut Bank<S>) -> HeathResult<()> {
+        match &self.snapshot {
+            Some(snapshot) => bank.resume(snapshot.clone()),
+            None => Ok(()),
+        }
+    }
+    /// Verify that the ledger at the given path starts with the rows of this history, ie: that
+    /// it was only appended to.
+    /// Fails with `HeathError::HistoryAltered` otherwise.
+    pub fn verify(&self, ledger: &Path) -> HeathResult<()> {
+        let (rows, hasher) = Self::hash(ledger, Some(self.rows))?;
+        if rows != self.rows || hex(&hasher.finalize()) != self.sha256 {
+            return Err(HeathError::HistoryAltered { rows: self.rows });
+        }
+        Ok(())
+    }
+    /// Load the history from the JSON file at the given path, if it exists.
+    pub fn load(path: &Path) -> HeathResult<Option<Self>> {
+        match std::fs::File::open(path) {
+            Ok(file) => Ok(Some(serde_json::from_reader(BufReader::new(file))?)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+    /// Write the history as JSON to the given path.
+    pub fn write(&self, path: &Path) -> HeathResult<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+    /// Hash up to `max_rows` rows of the ledger, regardless of their line endings, once
+    /// decompressed if it's gzip compressed.
+    fn hash(ledger: &Path, max_rows: Option<usize>) -> HeathResult<(usize, Sha256)> {
+        let mut hasher = Sha256::new();
+        let mut rows = 0;
+        for line in BufReader::new(crate::ledger::open(ledger)?).lines() {
+            if Some(rows) == max_rows {
+                break;
+            }
+            hasher.update(line?.trim_end_matches('\r'));
+            hasher.update("\n");
+            rows += 1;
+        }
+        Ok((rows, hasher))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use crate::{bank::Bank, error::HeathError, ledger::Ledger};
+
+    #[test]
+    fn append_only() -> anyhow::Result<()> {
+        let ledger = tempfile::NamedTempFile::new()?;
+        let ledger = ledger.path();
+        let rows = "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,5\n";
+        std::fs::write(ledger, rows)?;
+        let history = History::of(ledger)?;
+        assert_eq!(history.rows, 3);
+        history.verify(ledger)?;
+
+        // appending rows keeps the history
+        std::fs::write(ledger, format!("{}deposit,2,3,1\n", rows))?;
+        history.verify(ledger)?;
+
+        // editing a previously processed row alters it
+        std::fs::write(
+            ledger,
+            format!("{}deposit,2,3,1\n", rows.replace("10", "100")),
+        )?;
+        let error = history.verify(ledger).unwrap_err();
+        assert!(matches!(error, HeathError::HistoryAltered { rows: 3 }));
+
+        // as does truncating it
+        std::fs::write(ledger, "type,client,tx,amount\ndeposit,1,1,10\n")?;
+        let error = history.verify(ledger).unwrap_err();
+        assert!(matches!(error, HeathError::HistoryAltered { rows: 3 }));
+        Ok(())
+    }
+
+    #[test]
+    fn gzip() -> anyhow::Result<()> {
+        use std::io::Write;
+
+        let rows = "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,5\n";
+        let plain = tempfile::NamedTempFile::new()?;
+        std::fs::write(plain.path(), rows)?;
+        let compressed = tempfile::NamedTempFile::new()?;
+        let mut encoder =
+            flate2::write::GzEncoder::new(compressed.reopen()?, flate2::Compression::default());
+        encoder.write_all(rows.as_bytes())?;
+        encoder.finish()?;
+
+        // the rows are hashed once decompressed
+        let history = History::of(compressed.path())?;
+        assert_eq!(history.sha256, History::of(plain.path())?.sha256);
+        assert_eq!(history.rows, 3);
+        history.verify(compressed.path())?;
+        history.verify(plain.path())?;
+        Ok(())
+    }
+
+    #[test]
+    fn resume() -> anyhow::Result<()> {
+        let ledger = tempfile::NamedTempFile::new()?;
+        let ledger = ledger.path();
+        let rows = "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,5\n";
+        std::fs::write(ledger, rows)?;
+        let mut bank = Bank::new(
+            Ledger::from_path(ledger.into(), Default::default())?,
+            Default::default(),
+        );
+        bank.process()?;
+        let history = History::of(ledger)?.with_bank(&bank)?;
+
+        // only the appended rows are processed, on top of the recorded accounts
+        std::fs::write(ledger, format!("{}deposit,1,3,1\n", rows))?;
+        history.verify(ledger)?;
+        let mut bank = Bank::new(
+            Ledger::from_path(ledger.into(), Default::default())?,
+            Default::default(),
+        );
+        history.resume(&mut bank)?;
+        let stats = bank.process()?;
+        assert_eq!((stats.processed, stats.skipped), (3, 0));
+        assert_eq!(
+            bank.ordered_accounts_balance_buffer()?,
+            "client,available,held,total,locked\n1,6,0,6,false\n"
+        );
+        Ok(())
+    }
+}
@@ -1,26 +1,166 @@
-use crate::{csv::transaction::TransactionLogCsv, transaction::TransactionLog};
-use std::{fs::File, io::Seek, path::PathBuf};
+use crate::{
+    client::ClientId,
+    config::{AmountPrecision, InputFormat, LedgerConfig, SortKey},
+    csv::transaction::TransactionLogCsv,
+    error::{HeathError, HeathResult},
+    transaction::TransactionLog,
+    transactions::TransactionInfo,
+};
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+};
 
-#[derive(Debug)]
-pub(crate) struct Ledger {
-    csv_file: File,
+/// A reader of the ledger input
+type InputReader = Box<dyn Read + Send>;
+
+/// A stream of JSON transactions
+type JsonStream = serde_json::StreamDeserializer<
+    'static,
+    serde_json::de::IoRead<BufReader<InputReader>>,
+    TransactionLog,
+>;
+
+/// The magic bytes at the start of gzip compressed data
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// A ledger of transactions, read from csv or JSON
+/// # Csv Columns:
+/// The columns are matched by their header name, after the header map, in any order:
+/// - `type`, `client` and `tx` are required, though `tx` is optional for the administrative
+///   adjust, unlock, freeze and unfreeze transactions.
+/// - `amount` is required by the deposits, withdrawals, adjusts and transfers, `dest` by the
+///   transfers, and `percent` is optional for the disputes.
+///
+/// Rows may have fewer or more fields than the header: the fields past the header are ignored,
+/// with a warning if they're not empty, and a row missing a field its transaction requires, eg: a
+/// deposit without its `tx`, is skipped with a warning. A field which does not parse, eg: a `tx`
+/// which is not a number, fails with `HeathError::Parse` though.
+#[derive(Debug, Clone)]
+pub struct Ledger {
+    input: LedgerInput,
+    config: LedgerConfig,
+}
+
+/// The input of a `Ledger`, which is read from the start for each iteration
+#[derive(Clone)]
+enum LedgerInput {
+    /// A file, opened anew for each read.
+    File(PathBuf),
+    /// A gzip compressed file, opened and decompressed anew for each read.
+    Gzip(PathBuf),
+    /// An in-memory buffer of an input which cannot be re-read, eg: stdin.
+    Memory(Arc<[u8]>),
+}
+
+impl std::fmt::Debug for LedgerInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => f.debug_tuple("File").field(path).finish(),
+            Self::Gzip(path) => f.debug_tuple("Gzip").field(path).finish(),
+            Self::Memory(buffer) => f.debug_tuple("Memory").field(&buffer.len()).finish(),
+        }
+    }
+}
+
+impl LedgerInput {
+    /// Get the input of the file at the given path, which may be gzip compressed, as detected by
+    /// its magic bytes.
+    fn from_path(path: PathBuf) -> HeathResult<Self> {
+        // fail early if the file cannot be read
+        let mut magic = [0; 2];
+        let gzip = File::open(&path)?.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+        Ok(if gzip {
+            Self::Gzip(path)
+        } else {
+            Self::File(path)
+        })
+    }
+    /// Get the size of the input in bytes, if known, ie: not for gzip compressed input.
+    fn len(&self) -> HeathResult<Option<u64>> {
+        Ok(match self {
+            Self::File(path) => Some(std::fs::metadata(path)?.len()),
+            Self::Gzip(_) => None,
+            Self::Memory(buffer) => Some(buffer.len() as u64),
+        })
+    }
+    /// Open a new reader from the start of the input.
+    fn open(&self) -> HeathResult<InputReader> {
+        // open a new file for each reader rather than cloning the handle, as cloned handles share
+        // the same offset and would otherwise trip over each other, eg: when pipelining
+        Ok(match self {
+            Self::File(path) => Box::new(File::open(path)?),
+            Self::Gzip(path) => Box::new(flate2::read::GzDecoder::new(BufReader::new(File::open(
+                path,
+            )?))),
+            Self::Memory(buffer) => Box::new(std::io::Cursor::new(buffer.clone())),
+        })
+    }
+}
+
+/// Open a reader of the file at the given path, decompressed if it's gzip compressed, ie: of the
+/// same content a `Ledger` of the path reads.
+pub(crate) fn open(path: &Path) -> HeathResult<impl Read> {
+    LedgerInput::from_path(path.to_path_buf())?.open()
 }
 
 impl Ledger {
-    /// New `Self` from a given csv file
-    pub(crate) fn from_path(path: PathBuf) -> anyhow::Result<Self> {
-        let csv_file = File::open(path)?;
-        Ok(Self { csv_file })
-    }
-    fn reader(&self) -> anyhow::Result<csv::Reader<File>> {
-        let mut file = self.csv_file.try_clone()?;
-        file.rewind()?;
+    /// New `Self` from a given csv, or JSON, file and `LedgerConfig`
+    /// The file may be gzip compressed, as detected by its magic bytes.
+    /// Fails with `HeathError::InvalidConfig` if the decimal separator is the csv delimiter.
+    pub fn from_path(path: PathBuf, config: LedgerConfig) -> HeathResult<Self> {
+        Self::new(LedgerInput::from_path(path)?, config)
+    }
+    /// New `Self` from the whole content of the given reader, eg: stdin, which is buffered in
+    /// memory so that it can be read again by each iteration.
+    /// Gzip compressed content is decompressed into the buffer.
+    /// Fails with `HeathError::InvalidConfig` if the decimal separator is the csv delimiter.
+    pub fn from_reader(mut reader: impl Read, config: LedgerConfig) -> HeathResult<Self> {
+        let mut buffer = vec![];
+        reader.read_to_end(&mut buffer)?;
+        if buffer.starts_with(&GZIP_MAGIC) {
+            let mut decompressed = vec![];
+            flate2::read::GzDecoder::new(buffer.as_slice()).read_to_end(&mut decompressed)?;
+            buffer = decompressed;
+        }
+        Self::new(LedgerInput::Memory(buffer.into()), config)
+    }
+    /// New `Self` from the given in-memory transactions, eg: for testing or embedding.
+    /// Fails with `HeathError::InvalidConfig` if the decimal separator is the csv delimiter.
+    pub fn from_str(transactions: &str, config: LedgerConfig) -> HeathResult<Self> {
+        Self::new(LedgerInput::Memory(transactions.as_bytes().into()), config)
+    }
+    fn new(input: LedgerInput, config: LedgerConfig) -> HeathResult<Self> {
+        let delimiter = char::from(config.delimiter.unwrap_or(b','));
+        if config.decimal_separator == Some(delimiter) {
+            return Err(HeathError::InvalidConfig {
+                reason: format!("the decimal separator '{}' is the delimiter", delimiter),
+            });
+        }
+        Ok(Self { input, config })
+    }
+    /// Get the size of the ledger input in bytes, if known, ie: not for gzip compressed input.
+    pub(crate) fn len(&self) -> HeathResult<Option<u64>> {
+        self.input.len()
+    }
+    /// Get the csv reader of the ledger, which strips the leading UTF-8 BOM, if any, eg: of files
+    /// exported from spreadsheets, so that the headers still match.
+    fn reader(&self) -> HeathResult<csv::Reader<InputReader>> {
         let reader = csv::ReaderBuilder::new()
+            .delimiter(self.config.delimiter.unwrap_or(b','))
             .flexible(true)
             .trim(csv::Trim::All)
-            .from_reader(file);
+            .from_reader(self.input.open()?);
         Ok(reader)
     }
+    fn json_stream(&self) -> HeathResult<JsonStream> {
+        let input = BufReader::new(self.input.open()?);
+        Ok(serde_json::Deserializer::from_reader(input).into_iter())
+    }
     /// Print ledger transactions to stdout
     #[allow(dead_code)]
     pub(crate) fn print_transactions(&self) -> anyhow::Result<()> {
@@ -31,31 +171,954 @@ impl Ledger {
         }
         Ok(())
     }
+    /// Check the ledger for problems without processing it, stopping early once
+    /// `max_problems` have been found.
+    /// The ledger is read through the same path as its processing so that, besides the malformed
+    /// rows, the rows which processing would skip are reported, eg: negative amounts, or amounts
+    /// with too many decimal places when these are rejected.
+    /// A JSON ledger is only checked up to its first parse error, as the stream cannot be resumed
+    /// past it.
+    pub fn check(&self, max_problems: Option<usize>) -> HeathResult<LedgerCheck> {
+        let mut iter = self.iter()?;
+        let mut check = LedgerCheck::default();
+        loop {
+            if Some(check.problems.len()) == max_problems {
+                check.stopped_early = true;
+                break;
+            }
+            let transaction = match iter.next_transaction() {
+                Some(transaction) => transaction,
+                None => break,
+            };
+            let reason = match transaction {
+                Err(error) => {
+                    check.problems.push(error);
+                    continue;
+                }
+                Ok(transaction) if iter.excluded(&transaction) => continue,
+                Ok(transaction) if iter.over_precise(&transaction) => {
+                    "amount has more than 4 decimal places"
+                }
+                Ok(
+                    TransactionLog::Deposit { amount, .. }
+                    | TransactionLog::Withdrawal { amount, .. }
+                    | TransactionLog::Transfer { amount, .. },
+                ) if amount.is_sign_negative() && !amount.is_zero() => "amount is negative",
+                Ok(_) => continue,
+            };
+            check.problems.push(HeathError::InvalidTransaction {
+                line: iter.line,
+                reason: reason.to_string(),
+            });
+        }
+        Ok(check)
+    }
+    /// Check that the transactions are sorted by the given key, ie: that its column is
+    /// non-decreasing across the rows.
+    /// Fails with `HeathError::NotSorted` at the first, 1-based, row which is out of order.
+    pub fn check_sorted(&self, key: SortKey) -> HeathResult<()> {
+        let mut previous = None;
+        for (index, transaction) in self.iter()?.enumerate() {
+            let transaction = transaction?;
+            let value = match key {
+                SortKey::Client => u64::from(transaction.client_id()),
+                SortKey::Tx => u64::from(transaction.transaction_id()),
+            };
+            if previous.is_some_and(|previous| value < previous) {
+                return Err(HeathError::NotSorted {
+                    column: key.column(),
+                    row: index + 1,
+                });
+            }
+            previous = Some(value);
+        }
+        Ok(())
+    }
     /// Get a Ledger iterator
-    pub(crate) fn iter(&self) -> anyhow::Result<LedgerIter> {
+    pub(crate) fn iter(&self) -> HeathResult<LedgerIter> {
+        let source = match self.config.format {
+            InputFormat::Csv => {
+                let mut reader = self.reader()?;
+                let headers = reader.headers().map_err(parse_error)?.clone();
+                let parser = RecordParser::new(headers, self.config.clone());
+                LedgerSource::Csv { reader, parser }
+            }
+            InputFormat::Json => LedgerSource::Json(self.json_stream()?),
+        };
         Ok(LedgerIter {
-            reader: self.reader()?,
+            source,
+            amount_scale: self.config.amount_scale,
+            exclude_clients: self.config.exclude_clients.clone(),
+            amount_precision: self.config.amount_precision,
+            yielded: 0,
+            line: 0,
+            batch_markers: Default::default(),
         })
     }
 }
 
+/// Parses csv records into transactions, applying the `LedgerConfig` input transforms.
+/// The headers are renamed through the configured header map.
+#[derive(Debug)]
+struct RecordParser {
+    headers: csv::StringRecord,
+    type_column: Option<usize>,
+    client_column: Option<usize>,
+    amount_column: Option<usize>,
+    config: LedgerConfig,
+}
+
+impl RecordParser {
+    fn new(headers: csv::StringRecord, config: LedgerConfig) -> Self {
+        let headers = headers
+            .iter()
+            .map(|header| {
+                config
+                    .header_map
+                    .get(header)
+                    .map(String::as_str)
+                    .unwrap_or(header)
+            })
+            .collect::<csv::StringRecord>();
+        let type_column = headers.iter().position(|header| header == "type");
+        let client_column = headers.iter().position(|header| header == "client");
+        let amount_column = headers.iter().position(|header| header == "amount");
+        Self {
+            headers,
+            type_column,
+            client_column,
+            amount_column,
+            config,
+        }
+    }
+    /// Parse the csv record into a transaction, failing with `HeathError::Parse` if it's malformed
+    /// or with `HeathError::InvalidTransaction` if it's not a valid transaction, eg: a deposit
+    /// without an amount or a client id which does not fit a `ClientId`.
+    fn transaction(&self, record: &csv::StringRecord) -> HeathResult<TransactionLog> {
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        if let Some(client) = self.client_column.and_then(|column| record.get(column)) {
+            let client = client.trim();
+            if client.parse::<ClientId>().is_err() && client.parse::<u128>().is_ok() {
+                return Err(HeathError::InvalidTransaction {
+                    line,
+                    reason: format!(
+                        "client id {} is out of range, the maximum is {}",
+                        client,
+                        ClientId::MAX
+                    ),
+                });
+            }
+        }
+        let extra = record.iter().skip(self.headers.len());
+        if extra.clone().any(|field| !field.is_empty()) {
+            tracing::warn!(
+                line,
+                extra = extra.count(),
+                "Ignoring the fields past the header"
+            );
+        }
+        let transaction = self
+            .parse(record)
+            .map_err(|message| HeathError::Parse { line, message })?;
+        TransactionLog::try_from(transaction)
+            .map_err(|reason| HeathError::InvalidTransaction { line, reason })
+    }
+    /// Parse the csv record, remapping its type through the configured type map and its amount
+    /// through the configured decimal separator and, if allowed, from a ratio into a decimal.
+    fn parse(&self, record: &csv::StringRecord) -> Result<TransactionLogCsv, String> {
+        let mapped_type = self
+            .type_column
+            .and_then(|column| record.get(column))
+            .and_then(|type_| self.config.type_map.get(type_));
+        let mapped_amount = match self.amount_column.and_then(|column| record.get(column)) {
+            Some(amount) => self.map_amount(amount)?,
+            None => None,
+        };
+        if mapped_type.is_none() && mapped_amount.is_none() {
+            return record
+                .deserialize(Some(&self.headers))
+                .map_err(|error| error.to_string());
+        }
+        let mut mapped = record
+            .iter()
+            .enumerate()
+            .map(|(column, field)| match (mapped_type, &mapped_amount) {
+                (Some(mapped_type), _) if Some(column) == self.type_column => mapped_type.as_str(),
+                (_, Some(amount)) if Some(column) == self.amount_column => amount.as_str(),
+                _ => field,
+            })
+            .collect::<csv::StringRecord>();
+        mapped.set_position(record.position().cloned());
+        mapped
+            .deserialize(Some(&self.headers))
+            .map_err(|error| error.to_string())
+    }
+    /// Get the text of the record if it's a comment, ie: it starts with `#`, and comments are
+    /// enabled.
+    fn comment<'r>(&self, record: &'r csv::StringRecord) -> Option<&'r str> {
+        let first = record.get(0)?;
+        match first.strip_prefix('#') {
+            Some(comment) if self.config.comments => Some(comment.trim()),
+            _ => None,
+        }
+    }
+    /// Map the amount into the canonical decimal format, if it's not in it already.
+    fn map_amount(&self, amount: &str) -> Result<Option<String>, String> {
+        let amount = match self.config.decimal_separator {
+            Some(separator) if amount.contains(separator) => {
+                std::borrow::Cow::Owned(amount.replace(separator, "."))
+            }
+            _ => std::borrow::Cow::Borrowed(amount),
+        };
+        if self.config.allow_ratio_amounts && amount.contains('/') {
+            return Ok(Some(parse_ratio(&amount)?.to_string()));
+        }
+        Ok(match amount {
+            std::borrow::Cow::Owned(amount) => Some(amount),
+            std::borrow::Cow::Borrowed(_) => None,
+        })
+    }
+}
+
+/// Parse an `a/b` ratio amount into a decimal rounded to 4 decimal places, eg: 3/4 => 0.75.
+fn parse_ratio(ratio: &str) -> Result<rust_decimal::Decimal, String> {
+    let invalid = || format!("Invalid ratio amount {}", ratio);
+    let (numerator, denominator) = ratio.split_once('/').ok_or_else(invalid)?;
+    let numerator = numerator
+        .trim()
+        .parse::<rust_decimal::Decimal>()
+        .map_err(|_| invalid())?;
+    let denominator = denominator
+        .trim()
+        .parse::<rust_decimal::Decimal>()
+        .map_err(|_| invalid())?;
+    if denominator.is_zero() {
+        return Err(format!("Division by zero in the ratio amount {}", ratio));
+    }
+    numerator
+        .checked_div(denominator)
+        .map(|amount| amount.round_dp(4).normalize())
+        .ok_or_else(invalid)
+}
+
+/// The result of checking the ledger
+#[derive(Debug, Default)]
+pub struct LedgerCheck {
+    /// The problems found, each either a `HeathError::Parse` or `HeathError::InvalidTransaction`.
+    pub problems: Vec<HeathError>,
+    /// The check stopped at the maximum number of problems, so there may be more.
+    pub stopped_early: bool,
+}
+
+/// The start of a named batch of transactions, marked by a `# batch: <label>` comment
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BatchMarker {
+    /// Index of the first transaction of the batch, as yielded by the `LedgerIter`.
+    pub(crate) start: usize,
+    /// The batch label.
+    pub(crate) label: String,
+}
+
+/// The batch markers found so far by a `LedgerIter`, shared as it may be iterated on a separate
+/// thread when pipelining.
+pub(crate) type BatchMarkers = Arc<Mutex<Vec<BatchMarker>>>;
+
 /// Ledger iterator
 #[derive(Debug)]
 pub(crate) struct LedgerIter {
-    reader: csv::Reader<File>,
+    source: LedgerSource,
+    amount_scale: u32,
+    exclude_clients: Option<std::ops::RangeInclusive<ClientId>>,
+    amount_precision: AmountPrecision,
+    /// Number of transactions yielded so far.
+    yielded: usize,
+    /// Line of the last read csv record, or the position of the last read JSON value.
+    line: u64,
+    batch_markers: BatchMarkers,
+}
+
+/// The transactions source of a `LedgerIter`
+enum LedgerSource {
+    Csv {
+        reader: csv::Reader<InputReader>,
+        parser: RecordParser,
+    },
+    /// JSON transactions are deserialized straight into the `TransactionLog` enum.
+    Json(JsonStream),
+}
+
+impl std::fmt::Debug for LedgerSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Csv { reader, parser } => f
+                .debug_struct("Csv")
+                .field("position", reader.position())
+                .field("parser", parser)
+                .finish_non_exhaustive(),
+            Self::Json(_) => f.debug_tuple("Json").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl LedgerIter {
+    /// Get the number of bytes of the ledger input read so far.
+    pub(crate) fn position(&self) -> u64 {
+        match &self.source {
+            LedgerSource::Csv { reader, .. } => reader.position().byte(),
+            LedgerSource::Json(stream) => stream.byte_offset() as u64,
+        }
+    }
+    /// Get the batch markers, which are found as the ledger is iterated.
+    pub(crate) fn batch_markers(&self) -> BatchMarkers {
+        self.batch_markers.clone()
+    }
+    /// Iterate only the well formed transactions, skipping, with a warning, any malformed rows.
+    #[allow(dead_code)]
+    pub(crate) fn filter_ok(self) -> impl Iterator<Item = TransactionLog> {
+        self.filter_map(|transaction| match transaction {
+            Ok(transaction) => Some(transaction),
+            Err(error) => {
+                tracing::warn!(%error, "Skipping malformed transaction");
+                None
+            }
+        })
+    }
+    /// Move the iteration into a separate thread which sends the transactions through a channel
+    /// bounded to `capacity` transactions.
+    pub(crate) fn pipelined(
+        self,
+        capacity: usize,
+    ) -> (
+        JoinHandle<()>,
+        crossbeam_channel::Receiver<HeathResult<TransactionLog>>,
+    ) {
+        let (sender, receiver) = crossbeam_channel::bounded(capacity);
+        let reader = std::thread::spawn(move || {
+            for transaction in self {
+                if sender.send(transaction).is_err() {
+                    // the receiver has stopped processing
+                    break;
+                }
+            }
+        });
+        (reader, receiver)
+    }
 }
 
 impl Iterator for LedgerIter {
-    type Item = TransactionLog;
+    /// Each transaction, or the `HeathError::Parse` of the malformed row, with its 1-based line
+    /// number. Rows which are not valid transactions, eg: a deposit without an amount, are
+    /// skipped with a warning.
+    type Item = HeathResult<TransactionLog>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.reader.deserialize::<TransactionLogCsv>().next() {
-            None => None,
-            Some(Ok(transaction)) => Some(transaction.into()),
-            Some(Err(error)) => {
-                let error = anyhow::anyhow!("Error in the csv file!!!: {}", error);
-                panic!("{}", error);
+        loop {
+            let transaction = match self.next_transaction()? {
+                Ok(transaction) => transaction,
+                Err(error @ HeathError::InvalidTransaction { .. }) => {
+                    tracing::warn!(%error, "Skipping invalid transaction");
+                    continue;
+                }
+                Err(error) => {
+                    self.yielded += 1;
+                    return Some(Err(error));
+                }
+            };
+            match &transaction {
+                transaction if self.excluded(transaction) => {
+                    tracing::trace!(?transaction, "Excluded client");
+                }
+                transaction if self.over_precise(transaction) => {
+                    tracing::warn!(
+                        ?transaction,
+                        "Skipping amount with more than 4 decimal places"
+                    );
+                }
+                _ => {
+                    self.yielded += 1;
+                    return Some(Ok(transaction));
+                }
             }
         }
     }
 }
+
+impl LedgerIter {
+    /// The transaction's client is excluded from the processing.
+    fn excluded(&self, transaction: &TransactionLog) -> bool {
+        self.exclude_clients
+            .as_ref()
+            .is_some_and(|excluded| excluded.contains(&transaction.client_id()))
+    }
+    /// The transaction's amount has more decimal places than allowed, when these are rejected.
+    fn over_precise(&self, transaction: &TransactionLog) -> bool {
+        self.amount_precision == AmountPrecision::Reject
+            && transaction
+                .amount()
+                .is_some_and(|amount| amount.normalize().scale() > AmountPrecision::MAX_SCALE)
+    }
+    /// Read the next transaction from the source, with its amount scaled.
+    fn next_transaction(&mut self) -> Option<HeathResult<TransactionLog>> {
+        let transaction = match &mut self.source {
+            LedgerSource::Csv { reader, parser } => {
+                let mut record = csv::StringRecord::new();
+                let transaction = loop {
+                    match reader.read_record(&mut record) {
+                        Ok(false) => return None,
+                        Ok(true) => match parser.comment(&record) {
+                            None => {
+                                self.line = record.position().map(|p| p.line()).unwrap_or_default();
+                                break parser.transaction(&record);
+                            }
+                            Some(comment) => {
+                                if let Some(label) = comment.strip_prefix("batch:") {
+                                    self.batch_markers.lock().unwrap().push(BatchMarker {
+                                        start: self.yielded,
+                                        label: label.trim().to_string(),
+                                    });
+                                }
+                            }
+                        },
+                        Err(error) => break Err(parse_error(error)),
+                    }
+                };
+                transaction
+            }
+            LedgerSource::Json(stream) => {
+                self.line += 1;
+                stream.next()?.map_err(|error| HeathError::Parse {
+                    line: error.line() as u64,
+                    message: error.to_string(),
+                })
+            }
+        };
+        Some(transaction.map(|mut transaction| {
+            transaction.scale_amount(self.amount_scale);
+            transaction.set_line(self.line);
+            transaction
+        }))
+    }
+}
+
+/// Get the parse error of a csv record which could not be read, plainly identifying an input which
+/// is not UTF-8 encoded.
+fn parse_error(error: csv::Error) -> HeathError {
+    let line = error.position().map(|p| p.line()).unwrap_or_default();
+    let message = match error.kind() {
+        csv::ErrorKind::Utf8 { err, .. } => format!(
+            "the input is not valid UTF-8, field {} has invalid bytes",
+            err.field() + 1
+        ),
+        _ => error.to_string(),
+    };
+    HeathError::Parse { line, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{AmountPrecision, BankConfig, InputFormat, LedgerConfig, SortKey},
+        error::HeathError,
+        ledger::Ledger,
+    };
+
+    #[test]
+    fn io_error() {
+        let error = Ledger::from_path("./test_data/missing.csv".into(), LedgerConfig::default())
+            .unwrap_err();
+        assert!(matches!(error, HeathError::Io(_)));
+    }
+
+    #[test]
+    fn check_errors() -> anyhow::Result<()> {
+        let ledger = Ledger::from_path(
+            "./test_data/check/many_problems/input.csv".into(),
+            LedgerConfig::default(),
+        )?;
+        let check = ledger.check(Some(3))?;
+        assert!(matches!(
+            check.problems[0],
+            HeathError::InvalidTransaction { line: 3, .. }
+        ));
+        assert!(matches!(
+            check.problems[2],
+            HeathError::Parse { line: 5, .. }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn check_skipped() -> anyhow::Result<()> {
+        let input = "./test_data/validate/broken_row/input.csv";
+        let ledger_config = LedgerConfig {
+            amount_precision: AmountPrecision::Reject,
+            ..Default::default()
+        };
+        let check = Ledger::from_path(input.into(), ledger_config)?.check(None)?;
+        let lines = check
+            .problems
+            .iter()
+            .map(|problem| match problem {
+                HeathError::Parse { line, .. } => (*line, true),
+                HeathError::InvalidTransaction { line, .. } => (*line, false),
+                other => panic!("unexpected problem {}", other),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![(3, false), (4, true), (5, false), (6, false)]);
+
+        let ledger = Ledger::from_path(
+            "./test_data/deposit/ok/input.csv".into(),
+            Default::default(),
+        )?;
+        assert!(ledger.check(None)?.problems.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn bom() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/bom/ok");
+        let (expected, actual) = crate::bank::tests::test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_utf8() -> anyhow::Result<()> {
+        let input = "./test_data/bom/invalid_utf8/input.csv";
+        let ledger = Ledger::from_path(input.into(), LedgerConfig::default())?;
+        let check = ledger.check(None)?;
+        assert_eq!(check.problems.len(), 1);
+        match &check.problems[0] {
+            HeathError::Parse { line: 3, message } => {
+                assert!(message.contains("not valid UTF-8"), "{}", message)
+            }
+            other => panic!("unexpected error {}", other),
+        }
+
+        // the invalid row is skipped rather than aborting the run
+        let mut bank = crate::Bank::new(ledger, Default::default());
+        bank.process()?;
+        assert_eq!(bank.skipped("malformed_row"), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn from_str() -> anyhow::Result<()> {
+        let ledger = Ledger::from_str(
+            "\
+type,client,tx,amount
+deposit,1,1,2.5
+deposit,2,2,1
+withdrawal,1,3,1
+dispute,2,2,
+",
+            LedgerConfig::default(),
+        )?;
+        let mut bank = crate::Bank::new(ledger, Default::default());
+        assert_eq!(
+            bank.ordered_accounts_balance_buffer()?,
+            "client,available,held,total,locked\n1,1.5,0,1.5,false\n2,0,1,1,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn type_map() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/type_map/ok");
+        let ledger_config = LedgerConfig {
+            type_map: [
+                ("credit", "deposit"),
+                ("debit", "withdrawal"),
+                ("chargeoff", "chargeback"),
+            ]
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect(),
+            ..Default::default()
+        };
+        let (expected, actual) =
+            crate::bank::tests::test_with(test_folder, ledger_config.clone(), Default::default())?;
+        assert_eq!(expected, actual);
+
+        // unmapped types are still handled as unknown types, ie: they fail to parse
+        let ledger = Ledger::from_path(
+            "./test_data/type_map/unmapped/input.csv".into(),
+            ledger_config,
+        )?;
+        let check = ledger.check(None)?;
+        assert_eq!(check.problems.len(), 1);
+        assert!(matches!(
+            check.problems[0],
+            HeathError::Parse { line: 4, .. }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn ratio_amounts() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/ratio/ok");
+        let ledger_config = LedgerConfig {
+            allow_ratio_amounts: true,
+            ..Default::default()
+        };
+        let (expected, actual) =
+            crate::bank::tests::test_with(test_folder, ledger_config.clone(), Default::default())?;
+        assert_eq!(expected, actual);
+
+        let ledger = Ledger::from_path(
+            "./test_data/ratio/zero/input.csv".into(),
+            ledger_config.clone(),
+        )?;
+        let check = ledger.check(None)?;
+        assert_eq!(check.problems.len(), 1);
+        assert!(matches!(
+            &check.problems[0],
+            HeathError::Parse { line: 3, message } if message.contains("Division by zero")
+        ));
+        // the division by zero skips its row rather than aborting the run
+        let mut bank = crate::Bank::new(ledger, Default::default());
+        bank.process()?;
+        assert_eq!(bank.skipped("malformed_row"), 1);
+        assert_eq!(
+            bank.ordered_accounts_balance_buffer()?,
+            "client,available,held,total,locked\n1,0.75,0,0.75,false\n"
+        );
+
+        // ratios are not amounts unless allowed
+        let ledger = Ledger::from_path(test_folder.join("input.csv"), LedgerConfig::default())?;
+        assert_eq!(ledger.check(None)?.problems.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn exclude_clients() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/exclude_clients/ok");
+        let ledger_config = LedgerConfig {
+            exclude_clients: Some(900..=999),
+            ..Default::default()
+        };
+        let (expected, actual) =
+            crate::bank::tests::test_with(test_folder, ledger_config, Default::default())?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn amount_precision() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/precision/reject");
+        let ledger_config = LedgerConfig {
+            amount_precision: AmountPrecision::Reject,
+            ..Default::default()
+        };
+        let (expected, actual) =
+            crate::bank::tests::test_with(test_folder, ledger_config, Default::default())?;
+        assert_eq!(expected, actual);
+
+        // rounded rather than rejected by default
+        let ledger = Ledger::from_path(test_folder.join("input.csv"), LedgerConfig::default())?;
+        assert_eq!(ledger.iter()?.count(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn decimal_comma() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/decimal_comma/ok");
+        let ledger_config = LedgerConfig {
+            delimiter: Some(b';'),
+            decimal_separator: Some(','),
+            ..Default::default()
+        };
+        let (expected, actual) =
+            crate::bank::tests::test_with(test_folder, ledger_config, Default::default())?;
+        assert_eq!(expected, actual);
+
+        let ledger_config = LedgerConfig {
+            decimal_separator: Some(','),
+            ..Default::default()
+        };
+        let error = Ledger::from_path(test_folder.join("input.csv"), ledger_config).unwrap_err();
+        assert!(matches!(error, HeathError::InvalidConfig { .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn check_ok() -> anyhow::Result<()> {
+        let ledger = Ledger::from_path(
+            "./test_data/dispute/ok/input.csv".into(),
+            LedgerConfig::default(),
+        )?;
+        let check = ledger.check(None)?;
+        assert!(check.problems.is_empty());
+        assert!(!check.stopped_early);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_rows() -> anyhow::Result<()> {
+        let input = std::path::PathBuf::from("./test_data/check/many_problems/input.csv");
+        let ledger = Ledger::from_path(input.clone(), LedgerConfig::default())?;
+        let lines = ledger
+            .iter()?
+            .filter_map(|transaction| match transaction {
+                Err(HeathError::Parse { line, .. }) => Some(line),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // the rows missing their amount are skipped, leaving only the unparsable rows
+        assert_eq!(lines, vec![5, 6, 7, 10, 11, 12, 15, 16, 17]);
+        assert_eq!(ledger.iter()?.filter_ok().count(), 2);
+        assert_eq!(ledger.check(None)?.problems.len(), 15);
+
+        // processing skips the malformed rows, recording them in the error log
+        let config = BankConfig {
+            error_log: true,
+            ..Default::default()
+        };
+        let mut bank = crate::bank::tests::bank_with(input, LedgerConfig::default(), config)?;
+        let stats = bank.process()?;
+        assert_eq!((stats.processed, stats.skipped), (2, 9));
+        let lines = bank.error_log().iter().map(|entry| entry.line);
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            vec![5, 6, 7, 10, 11, 12, 15, 16, 17]
+        );
+        assert!(bank
+            .error_log()
+            .iter()
+            .all(|entry| entry.reason == "malformed_row" && entry.client.is_none()));
+        assert_eq!(
+            bank.ordered_accounts_balance_buffer()?,
+            "client,available,held,total,locked\n1,1,0,1,false\n2,2,0,2,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn extra_column() -> anyhow::Result<()> {
+        // the fields past the header are ignored rather than shifting the other fields
+        let test_folder = std::path::Path::new("./test_data/columns/extra_column");
+        let (expected, actual) = crate::bank::tests::test(test_folder)?;
+        assert_eq!(expected, actual);
+
+        let ledger = Ledger::from_path(test_folder.join("input.csv"), LedgerConfig::default())?;
+        assert!(ledger.check(None)?.problems.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn missing_tx() -> anyhow::Result<()> {
+        // the rows missing their tx are skipped, rather than failing the processing
+        let test_folder = std::path::Path::new("./test_data/columns/missing_tx");
+        let (expected, actual) = crate::bank::tests::test(test_folder)?;
+        assert_eq!(expected, actual);
+
+        let ledger = Ledger::from_path(test_folder.join("input.csv"), LedgerConfig::default())?;
+        let lines = ledger
+            .check(None)?
+            .problems
+            .iter()
+            .map(|problem| match problem {
+                HeathError::InvalidTransaction { line, .. } => Ok(*line),
+                problem => Err(problem.to_string()),
+            })
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(lines, Ok(vec![3, 5]));
+        Ok(())
+    }
+
+    #[test]
+    fn tab_delimiter() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/tsv/ok");
+        let ledger_config = LedgerConfig {
+            delimiter: Some(b'\t'),
+            ..Default::default()
+        };
+        let tsv = Ledger::from_path(test_folder.join("input.tsv"), ledger_config)?;
+        let csv = Ledger::from_path(test_folder.join("input.csv"), LedgerConfig::default())?;
+        let transactions = |ledger: &Ledger| -> anyhow::Result<Vec<String>> {
+            Ok(ledger
+                .iter()?
+                .map(|transaction| transaction.map(|transaction| format!("{:?}", transaction)))
+                .collect::<Result<_, _>>()?)
+        };
+        assert_eq!(transactions(&tsv)?, transactions(&csv)?);
+
+        // the output is comma separated regardless
+        let mut bank = crate::bank::Bank::new(tsv, Default::default());
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(bank.ordered_accounts_balance_buffer()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn gzip() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/gzip/ok");
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        let balance = |ledger: Ledger| {
+            crate::bank::Bank::new(ledger, Default::default()).ordered_accounts_balance_buffer()
+        };
+
+        let plain = Ledger::from_path(test_folder.join("input.csv"), LedgerConfig::default())?;
+        assert_eq!(balance(plain)?, expected);
+        let compressed =
+            Ledger::from_path(test_folder.join("input.csv.gz"), LedgerConfig::default())?;
+        assert_eq!(balance(compressed)?, expected);
+
+        // compressed stdin is decompressed into memory
+        let input = std::fs::File::open(test_folder.join("input.csv.gz"))?;
+        let ledger = Ledger::from_reader(input, LedgerConfig::default())?;
+        assert_eq!(balance(ledger)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn from_reader() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/dispute/ok");
+        let input = std::fs::read(test_folder.join("input.csv"))?;
+        let ledger = Ledger::from_reader(input.as_slice(), LedgerConfig::default())?;
+        // the buffered input can be iterated more than once, eg: when searching it
+        assert_eq!(ledger.iter()?.count(), ledger.iter()?.count());
+
+        let mut bank = crate::bank::Bank::new(ledger, Default::default());
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(bank.ordered_accounts_balance_buffer()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn check_sorted() -> anyhow::Result<()> {
+        let ledger = |case: &str| {
+            Ledger::from_path(
+                std::path::Path::new("./test_data")
+                    .join(case)
+                    .join("input.csv"),
+                LedgerConfig::default(),
+            )
+        };
+        let by_client = ledger("sorted_by_client/ok")?;
+        by_client.check_sorted(SortKey::Client)?;
+        // disputes carry the tx they reference, which is earlier
+        let error = by_client.check_sorted(SortKey::Tx).unwrap_err();
+        assert!(matches!(
+            error,
+            HeathError::NotSorted {
+                column: "tx",
+                row: 3
+            }
+        ));
+
+        let by_tx = ledger("deposit/ok")?;
+        by_tx.check_sorted(SortKey::Tx)?;
+        let error = by_tx.check_sorted(SortKey::Client).unwrap_err();
+        assert!(matches!(
+            error,
+            HeathError::NotSorted {
+                column: "client",
+                row: 3
+            }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn pipelined_bound() -> anyhow::Result<()> {
+        let ledger = Ledger::from_path(
+            "./test_data/dispute/report/input.csv".into(),
+            LedgerConfig::default(),
+        )?;
+
+        let expected = ledger.iter()?.count();
+        let (reader, transactions) = ledger.iter()?.pipelined(3);
+        assert_eq!(transactions.capacity(), Some(3));
+        for received in 0..expected {
+            assert!(transactions.len() <= 3);
+            // the reader blocks once the channel is full, so it cannot finish before all but the
+            // last 3 transactions are received
+            if received + 3 < expected {
+                assert!(!reader.is_finished());
+            }
+            transactions.recv()??;
+        }
+
+        assert!(transactions.recv().is_err());
+        reader.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn check_max_problems() -> anyhow::Result<()> {
+        let ledger = Ledger::from_path(
+            "./test_data/check/many_problems/input.csv".into(),
+            LedgerConfig::default(),
+        )?;
+
+        let check = ledger.check(None)?;
+        assert_eq!(check.problems.len(), 15);
+        assert!(!check.stopped_early);
+
+        let check = ledger.check(Some(10))?;
+        assert_eq!(check.problems.len(), 10);
+        assert!(check.stopped_early);
+        // stopped at the 10th problem rather than scanning the whole file
+        assert!(matches!(
+            check.problems.last(),
+            Some(HeathError::Parse { line: 12, .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn header_map() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/header_map/ok");
+        let ledger_config = LedgerConfig {
+            header_map: toml::from_str(&std::fs::read_to_string(test_folder.join("map.toml"))?)?,
+            ..Default::default()
+        };
+        let (expected, actual) =
+            crate::bank::tests::test_with(test_folder, ledger_config.clone(), Default::default())?;
+        assert_eq!(expected, actual);
+
+        // the same rows with the canonical headers parse into the same transactions
+        let input = std::fs::read_to_string(test_folder.join("input.csv"))?;
+        let canonical = input.replacen(
+            "txn_type,customer,reference,value",
+            "type,client,tx,amount",
+            1,
+        );
+        let mapped = Ledger::from_str(&input, ledger_config.clone())?;
+        let transactions = |ledger: Ledger| -> anyhow::Result<Vec<String>> {
+            Ok(ledger
+                .iter()?
+                .map(|transaction| transaction.map(|t| format!("{:?}", t)))
+                .collect::<Result<_, _>>()?)
+        };
+        assert_eq!(
+            transactions(mapped)?,
+            transactions(Ledger::from_str(&canonical, Default::default())?)?
+        );
+
+        // and with the header map configured the canonical headers still work
+        assert_eq!(
+            transactions(Ledger::from_str(&canonical, ledger_config)?)?,
+            transactions(Ledger::from_str(&canonical, Default::default())?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn json() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/json/mixed");
+        let ledger_config = LedgerConfig {
+            format: InputFormat::Json,
+            ..Default::default()
+        };
+        let ledger = Ledger::from_path(test_folder.join("input.json"), ledger_config)?;
+        assert!(ledger.check(None)?.problems.is_empty());
+
+        let mut bank = crate::Bank::new(ledger, Default::default());
+        let output = bank.ordered_accounts_balance_buffer()?;
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(expected.trim(), output.trim());
+        Ok(())
+    }
+}
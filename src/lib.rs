@@ -0,0 +1,67 @@
+//! Payments engine which applies a ledger of transactions, ie: deposits, withdrawals, disputes,
+//! resolves and chargebacks, into the accounts of its clients.
+//!
+//! A `Bank` processes a `Ledger`, read from a file or from memory, after which the balance of
+//! the accounts may be queried or written out:
+//! ```
+//! use ledger::{config::LedgerConfig, AccountInfo, Bank, Ledger};
+//! use rust_decimal_macros::dec;
+//!
+//! let transactions = "type,client,tx,amount\n\
+//!                     deposit,1,1,2.5\n\
+//!                     withdrawal,1,2,1\n";
+//! let ledger = Ledger::from_reader(transactions.as_bytes(), LedgerConfig::default())?;
+//! let mut bank = Bank::new(ledger, Default::default());
+//! bank.process()?;
+//!
+//! let account = bank.account_balance(1)?.expect("client 1 has an account");
+//! assert_eq!(account.available_funds(), dec!(1.5));
+//! assert!(bank.account_balance(2)?.is_none());
+//! # Ok::<(), ledger::HeathError>(())
+//! ```
+
+mod account;
+mod bank;
+mod client;
+pub mod config;
+mod csv;
+mod error;
+pub mod fixed_width;
+pub mod history;
+mod ledger;
+pub mod manifest;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod store;
+mod table;
+mod transaction;
+mod transactions;
+
+pub use crate::{
+    account::{Account, AccountAnomaly, AccountId, AccountInfo},
+    bank::{Bank, BatchSummary, DisputeEvent, GlobalReconcile, ProcessStats, Progress},
+    client::ClientId,
+    csv::{
+        account::{resume_output, AccountLog},
+        dispute::{DisputeLog, DisputeLogState},
+        event::{AccountEvent, AccountEventKind},
+        statement::StatementLog,
+        transaction::{TransactionId, TransactionLogCsv, TransactionType},
+    },
+    error::{HeathError, HeathResult},
+    ledger::{Ledger, LedgerCheck},
+    transaction::{
+        DisputeOutcome, DisputeSate, TransactionLog, TransactionLogCommon, TransactionState,
+    },
+};
+
+/// Initialize the tracing subscriber, filtered by the `RUST_LOG` environment variable.
+pub fn init_tracing() -> anyhow::Result<()> {
+    let filter = tracing_subscriber::EnvFilter::from_default_env();
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .pretty()
+        .try_init()
+        .map_err(|_| anyhow::anyhow!("Failed to init tracing (already inited?)"))?;
+    Ok(())
+}
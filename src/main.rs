@@ -1,43 +1,840 @@
-mod account;
-mod bank;
-mod client;
-mod csv;
-mod ledger;
-mod transaction;
-mod transactions;
-
-use crate::{bank::Bank, ledger::Ledger};
-use std::path::PathBuf;
+use itertools::Itertools;
+use ledger::{
+    config::{
+        AmountPrecision, BankConfig, BoolFormat, DisputePolicy, DuplicatePolicy, DuplicateScope,
+        FrozenBehavior, FrozenDepositPolicy, InputFormat, LedgerConfig, LogFields, OutputFormat,
+        RepeatedDisputePolicy, Rounding, SortBy, SortKey,
+    },
+    fixed_width::Layout,
+    history::History,
+    init_tracing,
+    manifest::HashingWriter,
+    resume_output,
+    store::AccountStore,
+    Bank, ClientId, HeathError, Ledger, ProcessStats, TransactionLogCsv, TransactionType,
+};
+use std::{
+    collections::HashMap,
+    io::{BufWriter, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use structopt::StructOpt;
 
 #[derive(structopt::StructOpt, Debug)]
 struct CliArgs {
-    /// Transactions file in a csv format.
+    /// Transactions file in a csv format, optionally gzip compressed, or - to read from stdin.
     #[structopt(name = "transactions")]
-    transactions: PathBuf,
+    transactions: Option<PathBuf>,
+    /// Fail if the output would contain more than one row for the same client.
+    #[structopt(long)]
+    assert_unique_clients: bool,
+    /// Fail if the output total of an account differs from its output available plus held funds.
+    #[structopt(long)]
+    check_invariants: bool,
+    /// Format of the transactions file: csv, or a stream of JSON objects tagged by their type.
+    #[structopt(long, default_value = "csv", possible_values = &["csv", "json"])]
+    input_format: String,
+    /// Input amounts are given as integer minor units and are divided by 10^N when parsed.
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_amount_scale))]
+    input_amount_scale: u32,
+    /// Amounts with more than 4 decimal places are either rounded, or rejected, skipping their
+    /// transaction with a warning.
+    #[structopt(long, default_value = "round", possible_values = &["round", "reject"])]
+    amount_precision: String,
+    /// Remap input transaction types into the canonical types, eg: credit=deposit,debit=withdrawal.
+    #[structopt(long, parse(try_from_str = parse_type_map))]
+    type_map: Option<HashMap<String, String>>,
+    /// Exclude the transactions of the clients within the inclusive range, eg: 900-999.
+    #[structopt(long, parse(try_from_str = parse_client_range))]
+    exclude_test_clients: Option<RangeInclusive<ClientId>>,
+    /// Delimiter of the input csv fields, eg: ';', or '\t' for tab separated values.
+    /// The output is always comma separated.
+    #[structopt(
+        long,
+        alias = "delimiter",
+        default_value = ",",
+        parse(try_from_str = parse_delimiter)
+    )]
+    input_delimiter: u8,
+    /// Decimal separator of the input amounts, eg: ',' for 1,50 within a ';' delimited file.
+    #[structopt(long, default_value = ".")]
+    input_decimal_separator: char,
+    /// Parse amounts given as `a/b` ratios, eg: 3/4 is 0.75, rounded to 4 decimal places.
+    #[structopt(long)]
+    allow_ratio_amounts: bool,
+    /// Toml file mapping input column names into the canonical type, client, tx, amount, percent
+    /// and dest names.
+    #[structopt(long, parse(try_from_str = parse_header_map))]
+    header_map: Option<HashMap<String, String>>,
+    /// Abort processing after the given number of seconds, emitting the accounts processed so far.
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    deadline: Option<Duration>,
+    /// Write the accounts to the given file rather than to stdout.
+    #[structopt(long, short)]
+    output: Option<PathBuf>,
+    /// Flush the csv output every this many accounts, so that a long write is observable, or only
+    /// once all are written if 0.
+    #[structopt(long, default_value = "0")]
+    flush_every: usize,
+    /// Format of the accounts output, where json is an array of the accounts and table an aligned
+    /// ASCII table, for terminals.
+    #[structopt(
+        long,
+        alias = "format",
+        default_value = "csv",
+        possible_values = &["csv", "json", "table", "fixed-width"]
+    )]
+    output_format: String,
+    /// Toml file declaring the fixed-width record layout, required by the fixed-width format.
+    #[structopt(long, required_if("output-format", "fixed-width"), parse(try_from_str = parse_layout))]
+    layout: Option<Layout>,
+    /// Print the number of transactions processed and skipped to stderr after processing.
+    #[structopt(long)]
+    print_processed: bool,
+    /// Print a summary of the rows and net funds moved of each batch to stderr after processing.
+    /// Batches start at `# batch: <label>` comment lines, with any other `#` lines ignored.
+    #[structopt(long)]
+    batch_summaries: bool,
+    /// Print the total funds across the whole bank to stderr after processing.
+    #[structopt(long)]
+    bank_total: bool,
+    /// Print the expected and actual bank-wide total funds to stderr, warning on a mismatch.
+    #[structopt(long)]
+    global_reconcile: bool,
+    /// Print the accounts whose funds are inconsistent to stderr.
+    #[structopt(long)]
+    report_anomalies: bool,
+    /// Print the clients whose accounts exist without any deposit or withdrawal to stderr.
+    #[structopt(long)]
+    report_orphans: bool,
+    /// Print the clients whose accounts are locked to stderr.
+    #[structopt(long)]
+    report_locked: bool,
+    /// Only check the transactions for problems, without computing the balances, as the `check`
+    /// subcommand does.
+    #[structopt(long)]
+    validate: bool,
+    /// Log, at the info level, the approximate progress through the transactions file while
+    /// processing it.
+    #[structopt(long)]
+    progress: bool,
+    /// Print the number of transactions processed per type, and ignored per reason, to stderr
+    /// after the accounts output.
+    #[structopt(long)]
+    stats: bool,
+    /// Write a csv report of the ongoing and charged back disputes to the given path.
+    #[structopt(long)]
+    dispute_report: Option<PathBuf>,
+    /// Write each change of an account's funds, as it's applied, to the given csv path.
+    #[structopt(long)]
+    events: Option<PathBuf>,
+    /// Write each ignored transaction, with the reason why, to the given csv path.
+    #[structopt(long)]
+    error_log: Option<PathBuf>,
+    /// Write the index of deposits and withdrawals to the given csv path.
+    #[structopt(long)]
+    dump_index: Option<PathBuf>,
+    /// Write the accounts into the `accounts` table of the given sqlite database.
+    #[cfg(feature = "sqlite")]
+    #[structopt(long, conflicts_with = "sorted-by-client")]
+    output_sqlite: Option<PathBuf>,
+    /// The transactions are sorted by client: stream out each client's account as soon as its
+    /// transactions end, keeping only a single account in memory. Disputes may only reference
+    /// transactions of the same client. The accounts are only written as csv, and the reports
+    /// which need all of the accounts, or transactions, cannot be requested.
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "output-format",
+            "layout",
+            "events",
+            "error-log",
+            "dispute-report",
+            "dump-index",
+            "report-anomalies",
+            "report-orphans",
+            "report-locked",
+            "global-reconcile",
+            "bank-total",
+            "batch-summaries",
+            "progress",
+        ]
+    )]
+    sorted_by_client: bool,
+    /// Fail, before producing any output, if the transactions are not sorted by the given column.
+    #[structopt(long, possible_values = &["client", "tx"])]
+    require_sorted_by: Option<String>,
+    /// Resume a streamed output at the given path, eg: after a crash, appending only the accounts
+    /// of the clients after the last one already written.
+    #[structopt(long, requires = "sorted-by-client")]
+    append: Option<PathBuf>,
+    /// Treat the ledger as append-only, failing if the rows processed by a previous run, as
+    /// recorded in the JSON history file at the given path, were edited since. Processing then
+    /// resumes from the accounts recorded by the history, skipping those rows. The history is
+    /// updated once the ledger is processed.
+    #[structopt(long, conflicts_with = "sorted-by-client")]
+    history: Option<PathBuf>,
+    /// Write a JSON manifest of the output to the given path, with the SHA-256 of the output
+    /// bytes, the number of rows and the tool version.
+    #[structopt(long, conflicts_with = "append")]
+    manifest: Option<PathBuf>,
+    /// Parse the transactions on a separate thread, pipelining the reads with the processing.
+    #[structopt(long)]
+    pipeline: bool,
+    /// Maximum number of parsed transactions queued up when pipelining.
+    #[structopt(long, default_value = "1024")]
+    pipeline_capacity: usize,
+    /// Partition the transactions by client and process the partitions on this many threads.
+    /// Cannot be combined with cross-client disputes, max disputes or batch summaries.
+    #[structopt(long, default_value = "1")]
+    jobs: usize,
+    /// Allow disputes, resolves and chargebacks to reference transactions of other clients.
+    #[structopt(long)]
+    cross_client_disputes: bool,
+    /// Withdrawals exceeding the available funds drain them to zero rather than being rejected.
+    #[structopt(long)]
+    partial_withdrawals: bool,
+    /// Toml file mapping clients into their overdraft limit, eg: 1 = "100", down to which their
+    /// withdrawals may take the available funds below zero.
+    #[structopt(long, parse(try_from_str = parse_overdraft_limits))]
+    overdraft_limits: Option<HashMap<ClientId, rust_decimal::Decimal>>,
+    /// A deposit with the client and tx of an earlier deposit adds its amount to the earlier one,
+    /// so that a dispute of the tx holds the combined amount.
+    #[structopt(long)]
+    merge_duplicate_deposits: bool,
+    /// What happens to a deposit or withdrawal reusing the tx id of an earlier one: apply it with
+    /// a warning, or ignore it.
+    #[structopt(long, default_value = "warn", possible_values = &["warn", "ignore"])]
+    duplicate_policy: String,
+    /// Whether the tx ids are unique per client, or across all clients.
+    #[structopt(long, default_value = "client", possible_values = &["client", "global"])]
+    duplicate_scope: String,
+    /// Output the highest total funds each account reached as an extra `peak_total` column.
+    #[structopt(long)]
+    with_peak: bool,
+    /// Omit the accounts only opened by a dispute, resolve or chargeback of an unknown
+    /// transaction, which have no funds and are not locked, from the output.
+    #[structopt(long)]
+    omit_empty: bool,
+    /// The account field the output is ordered by, with ties ordered by client.
+    #[structopt(long, default_value = "client", possible_values = &["client", "available", "held", "total"])]
+    sort_by: String,
+    /// Order the output descending rather than ascending.
+    #[structopt(long)]
+    desc: bool,
+    /// How the locked column is represented in the output: true/false, 1/0 or Y/N.
+    #[structopt(long, default_value = "true-false", possible_values = &["true-false", "0-1", "yes-no"])]
+    bool_format: String,
+    /// Round the output amounts to this many decimal places, 4 by default.
+    #[structopt(long)]
+    precision: Option<u32>,
+    /// Output the amounts with exactly the precision's decimal places, eg: 2.0000 rather than 2.
+    #[structopt(long)]
+    fixed_decimals: bool,
+    /// Skip withdrawals of clients without an account rather than opening an empty account.
+    #[structopt(long)]
+    no_phantom_on_withdrawal: bool,
+    /// Make resolves final, ignoring later disputes of the resolved transaction rather than
+    /// disputing it again.
+    #[structopt(long)]
+    no_redispute: bool,
+    /// Resolves and chargebacks whose disputed amount exceeds the held funds by no more than this,
+    /// eg: by a rounding crumb, release the held funds rather than being ignored.
+    #[structopt(long, default_value = "0")]
+    held_epsilon: rust_decimal::Decimal,
+    /// Skip any disputes, resolves and chargebacks after this many have been applied.
+    #[structopt(long)]
+    max_disputes: Option<usize>,
+    /// A chargeback of an undisputed deposit implicitly disputes and then charges it back.
+    #[structopt(long)]
+    implicit_dispute_on_chargeback: bool,
+    /// Once an account is locked by a chargeback skip all of its later transactions.
+    #[structopt(long)]
+    stop_at_first_chargeback: bool,
+    /// Keep the full precision internally and round only on the output.
+    #[structopt(long)]
+    round_at_output_only: bool,
+    /// Never round the amounts, preserving whatever precision the input carried.
+    #[structopt(long, conflicts_with = "round-at-output-only")]
+    no_rounding: bool,
+    /// How a dispute of an already disputed transaction affects its held funds: ignore it, stack
+    /// its hold on top of the prior hold, up to the transaction's amount, or replace the prior
+    /// hold.
+    #[structopt(long, default_value = "ignore", possible_values = &["ignore", "stack", "replace"])]
+    repeated_disputes: String,
+    /// How a dispute of more than the available funds is handled: skip it, or hold the whole
+    /// amount allowing the available funds to go negative.
+    #[structopt(
+        long,
+        default_value = "skip-if-insufficient",
+        possible_values = &["skip-if-insufficient", "allow-negative"]
+    )]
+    dispute_policy: String,
+    /// Whether disputes, resolves and chargebacks still apply to frozen accounts, or are blocked
+    /// like deposits and withdrawals.
+    #[structopt(
+        long,
+        default_value = "allow-disputes",
+        possible_values = &["allow-disputes", "block-all"]
+    )]
+    frozen_behavior: String,
+    /// Whether deposits to frozen accounts are blocked, or still credited with only the funds
+    /// leaving the account blocked.
+    #[structopt(long, default_value = "block", possible_values = &["block", "allow"])]
+    frozen_deposit_policy: String,
+    /// Which account fields are recorded by the transaction logs: minimal records only the client
+    /// id and the funds, full records the whole account including its disputes.
+    #[structopt(long, default_value = "full", possible_values = &["minimal", "full"])]
+    log_fields: String,
+    /// Store the accounts on disk, in the sled database at the given path, rather than in memory.
+    #[cfg(feature = "sled")]
+    #[structopt(long)]
+    accounts_db: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-fn init_tracing() -> anyhow::Result<()> {
-    let filter = tracing_subscriber::EnvFilter::from_default_env();
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .pretty()
-        .try_init()
-        .map_err(|_| anyhow::anyhow!("Failed to init tracing (already inited?)"))?;
-    Ok(())
+#[derive(structopt::StructOpt, Debug)]
+enum Command {
+    /// Check the transactions file for problems without computing the balances.
+    Check {
+        /// Stop after finding this many problems.
+        #[structopt(long)]
+        max_problems: Option<usize>,
+        /// Transactions file in a csv format, optionally gzip compressed, or - to read from stdin.
+        transactions: PathBuf,
+    },
+    /// Print the statement of a client's transactions with the running available funds.
+    Statement {
+        /// The client whose statement is printed.
+        #[structopt(long)]
+        client: ClientId,
+        /// Transactions file in a csv format, optionally gzip compressed, or - to read from stdin.
+        transactions: PathBuf,
+    },
+}
+
+/// Parse a `Duration` from a number of seconds.
+fn parse_seconds(src: &str) -> anyhow::Result<Duration> {
+    Ok(Duration::try_from_secs_f64(src.parse::<f64>()?)?)
+}
+
+/// Parse a comma separated list of `from=to` transaction type mappings.
+fn parse_type_map(src: &str) -> anyhow::Result<HashMap<String, String>> {
+    src.split(',')
+        .map(|mapping| match mapping.split_once('=') {
+            Some((from, to)) => {
+                let to = to.trim();
+                to.parse::<TransactionType>()?;
+                Ok((from.trim().to_string(), to.to_string()))
+            }
+            None => anyhow::bail!("Invalid type mapping '{}', expected from=to", mapping),
+        })
+        .collect()
+}
+
+/// Parse a single ASCII character csv delimiter, with `\t` standing for a tab.
+fn parse_delimiter(src: &str) -> anyhow::Result<u8> {
+    match src.as_bytes() {
+        b"\\t" => Ok(b'\t'),
+        [delimiter] if delimiter.is_ascii() => Ok(*delimiter),
+        _ => anyhow::bail!(
+            "Invalid delimiter '{}', expected a single ASCII character",
+            src
+        ),
+    }
+}
+
+/// Parse an inclusive `from-to` range of clients.
+fn parse_client_range(src: &str) -> anyhow::Result<RangeInclusive<ClientId>> {
+    match src.split_once('-') {
+        Some((from, to)) => {
+            let (from, to): (ClientId, ClientId) = (from.trim().parse()?, to.trim().parse()?);
+            if from > to {
+                anyhow::bail!("Invalid client range '{}', {} is after {}", src, from, to);
+            }
+            Ok(from..=to)
+        }
+        None => anyhow::bail!("Invalid client range '{}', expected from-to", src),
+    }
+}
+
+/// Parse the toml file at the given path, mapping input column names into the canonical names.
+fn parse_header_map(src: &str) -> anyhow::Result<HashMap<String, String>> {
+    let header_map: HashMap<String, String> = toml::from_str(&std::fs::read_to_string(src)?)?;
+    const CANONICAL: [&str; 6] = ["type", "client", "tx", "amount", "percent", "dest"];
+    if let Some(to) = header_map
+        .values()
+        .find(|to| !CANONICAL.contains(&to.as_str()))
+    {
+        anyhow::bail!(
+            "Invalid header mapping to '{}', expected one of {:?}",
+            to,
+            CANONICAL
+        );
+    }
+    Ok(header_map)
+}
+
+/// Parse the toml file at the given path, mapping clients into their overdraft limit.
+fn parse_overdraft_limits(src: &str) -> anyhow::Result<HashMap<ClientId, rust_decimal::Decimal>> {
+    let limits: HashMap<String, rust_decimal::Decimal> =
+        toml::from_str(&std::fs::read_to_string(src)?)?;
+    limits
+        .into_iter()
+        .map(|(client, limit)| {
+            let client = client
+                .parse::<ClientId>()
+                .map_err(|_| anyhow::anyhow!("Invalid overdraft client '{}'", client))?;
+            if limit.is_sign_negative() {
+                anyhow::bail!("Negative overdraft limit {} of client {}", limit, client);
+            }
+            Ok((client, limit))
+        })
+        .collect()
+}
+
+/// Parse the fixed-width record layout from the toml file at the given path.
+fn parse_layout(src: &str) -> anyhow::Result<Layout> {
+    Ok(toml::from_str(&std::fs::read_to_string(src)?)?)
+}
+
+/// Parse the input amount scale, which is limited by the `rust_decimal::Decimal` precision.
+fn parse_amount_scale(src: &str) -> anyhow::Result<u32> {
+    let scale = src.parse::<u32>()?;
+    if scale > 28 {
+        anyhow::bail!("Amount scale {} exceeds the maximum of 28", scale);
+    }
+    Ok(scale)
+}
+
+impl CliArgs {
+    /// Get the `LedgerConfig` from the command line arguments
+    fn ledger_config(&self) -> LedgerConfig {
+        LedgerConfig {
+            amount_scale: self.input_amount_scale,
+            type_map: self.type_map.clone().unwrap_or_default(),
+            allow_ratio_amounts: self.allow_ratio_amounts,
+            delimiter: Some(self.input_delimiter),
+            decimal_separator: Some(self.input_decimal_separator)
+                .filter(|separator| *separator != '.'),
+            exclude_clients: self.exclude_test_clients.clone(),
+            comments: self.batch_summaries,
+            header_map: self.header_map.clone().unwrap_or_default(),
+            format: match self.input_format.as_str() {
+                "json" => InputFormat::Json,
+                _ => InputFormat::Csv,
+            },
+            amount_precision: match self.amount_precision.as_str() {
+                "reject" => AmountPrecision::Reject,
+                _ => AmountPrecision::Round,
+            },
+        }
+    }
+    /// Get the `BankConfig` from the command line arguments
+    fn bank_config(&self) -> BankConfig {
+        BankConfig {
+            assert_unique_clients: self.assert_unique_clients,
+            check_invariants: self.check_invariants,
+            deadline: self.deadline,
+            pipeline_capacity: self.pipeline.then_some(self.pipeline_capacity),
+            jobs: Some(self.jobs),
+            cross_client_disputes: self.cross_client_disputes,
+            statement_client: None,
+            error_log: self.error_log.is_some(),
+            partial_withdrawals: self.partial_withdrawals,
+            overdraft_limits: self.overdraft_limits.clone().unwrap_or_default(),
+            no_phantom_on_withdrawal: self.no_phantom_on_withdrawal,
+            no_redispute: self.no_redispute,
+            merge_duplicate_deposits: self.merge_duplicate_deposits,
+            duplicate_policy: match self.duplicate_policy.as_str() {
+                "ignore" => DuplicatePolicy::Ignore,
+                _ => DuplicatePolicy::Warn,
+            },
+            duplicate_scope: match self.duplicate_scope.as_str() {
+                "global" => DuplicateScope::Global,
+                _ => DuplicateScope::Client,
+            },
+            with_peak: self.with_peak,
+            output_format: match self.output_format.as_str() {
+                "json" => OutputFormat::Json,
+                "table" => OutputFormat::Table,
+                _ => OutputFormat::Csv,
+            },
+            omit_empty: self.omit_empty,
+            sort_by: match self.sort_by.as_str() {
+                "available" => SortBy::Available,
+                "held" => SortBy::Held,
+                "total" => SortBy::Total,
+                _ => SortBy::Client,
+            },
+            descending: self.desc,
+            bool_format: match self.bool_format.as_str() {
+                "0-1" => BoolFormat::OneZero,
+                "yes-no" => BoolFormat::YesNo,
+                _ => BoolFormat::TrueFalse,
+            },
+            output_precision: self.precision,
+            fixed_decimals: self.fixed_decimals,
+            max_disputes: self.max_disputes,
+            implicit_dispute_on_chargeback: self.implicit_dispute_on_chargeback,
+            held_epsilon: self.held_epsilon,
+            stop_at_first_chargeback: self.stop_at_first_chargeback,
+            batch_summaries: self.batch_summaries,
+            dispute_policy: match self.dispute_policy.as_str() {
+                "allow-negative" => DisputePolicy::AllowNegative,
+                _ => DisputePolicy::SkipIfInsufficient,
+            },
+            frozen_behavior: match self.frozen_behavior.as_str() {
+                "block-all" => FrozenBehavior::BlockAll,
+                _ => FrozenBehavior::AllowDisputes,
+            },
+            frozen_deposit_policy: match self.frozen_deposit_policy.as_str() {
+                "allow" => FrozenDepositPolicy::Allow,
+                _ => FrozenDepositPolicy::Block,
+            },
+            repeated_dispute_policy: match self.repeated_disputes.as_str() {
+                "stack" => RepeatedDisputePolicy::Stack,
+                "replace" => RepeatedDisputePolicy::Replace,
+                _ => RepeatedDisputePolicy::Ignore,
+            },
+            log_fields: match self.log_fields.as_str() {
+                "minimal" => LogFields::Minimal,
+                _ => LogFields::Full,
+            },
+            rounding: if self.no_rounding {
+                Rounding::Never
+            } else if self.round_at_output_only {
+                Rounding::OutputOnly
+            } else {
+                Rounding::PerOperation
+            },
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let args = CliArgs::from_args();
     init_tracing()?;
 
-    let ledger = Ledger::from_path(args.transactions)?;
+    let transactions = match &args.command {
+        Some(Command::Check {
+            max_problems,
+            transactions,
+        }) => {
+            return check(&ledger(transactions, &args)?, *max_problems);
+        }
+        Some(Command::Statement {
+            client,
+            transactions,
+        }) => {
+            let ledger = ledger(transactions, &args)?;
+            let config = BankConfig {
+                statement_client: Some(*client),
+                ..args.bank_config()
+            };
+            return statement(Bank::new(ledger, config));
+        }
+        None => args
+            .transactions
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("The transactions file is required"))?,
+    };
+
+    if args.validate {
+        return check(&ledger(&transactions, &args)?, None);
+    }
+    if args.history.is_some() && transactions == Path::new(STDIN) {
+        anyhow::bail!("The history requires a transactions file rather than stdin");
+    }
+    // only the rows appended since the previous run may differ
+    let history = match &args.history {
+        Some(path) => History::load(path)?,
+        None => None,
+    };
+    if let Some(history) = &history {
+        history.verify(&transactions)?;
+    }
+    let ledger = ledger(&transactions, &args)?;
     // ledger.print_transactions()?;
+    if let Some(key) = &args.require_sorted_by {
+        ledger.check_sorted(match key.as_str() {
+            "tx" => SortKey::Tx,
+            _ => SortKey::Client,
+        })?;
+    }
 
-    let mut bank = Bank::new(ledger);
+    #[cfg(feature = "sled")]
+    if let Some(path) = &args.accounts_db {
+        let store = ledger::store::SledStore::open(path)?;
+        let mut bank = Bank::with_store(ledger, args.bank_config(), store);
+        return run_history(&args, &transactions, history, &mut bank);
+    }
+    let mut bank = Bank::new(ledger, args.bank_config());
+    run_history(&args, &transactions, history, &mut bank)
+}
+
+/// Number of transactions processed between progress logs
+const PROGRESS_INTERVAL: usize = 100_000;
+
+/// The transactions path which reads them from stdin.
+const STDIN: &str = "-";
+
+/// Open the ledger of the given transactions path, reading it from stdin if the path is `-`.
+fn ledger(transactions: &Path, args: &CliArgs) -> anyhow::Result<Ledger> {
+    if transactions == Path::new(STDIN) {
+        return Ok(Ledger::from_reader(
+            std::io::stdin().lock(),
+            args.ledger_config(),
+        )?);
+    }
+    Ok(Ledger::from_path(
+        transactions.to_path_buf(),
+        args.ledger_config(),
+    )?)
+}
+
+/// Process the bank, resuming from the rows processed as per the `history` of a previous run, if
+/// any, and record the history of the processed ledger, if requested.
+fn run_history<S: AccountStore>(
+    args: &CliArgs,
+    transactions: &Path,
+    history: Option<History>,
+    bank: &mut Bank<S>,
+) -> anyhow::Result<()> {
+    if let Some(history) = history {
+        history.resume(bank)?;
+    }
+    run(args, bank)?;
+    if let Some(path) = &args.history {
+        History::of(transactions)?.with_bank(bank)?.write(path)?;
+    }
+    Ok(())
+}
+
+/// Print the processed transactions per type and the ignored ones per reason to stderr.
+fn print_stats(stats: &ProcessStats) {
+    for (kind, count) in &stats.processed_by_type {
+        eprintln!("{:?}: {}", kind, count);
+    }
+    for (category, count) in &stats.skipped_by_category {
+        eprintln!("ignored {}: {}", category, count);
+    }
+}
+
+/// Process the bank and write out the accounts and any other requested outputs.
+fn run<S: AccountStore>(args: &CliArgs, bank: &mut Bank<S>) -> anyhow::Result<()> {
+    if args.sorted_by_client {
+        let stats = match &args.append {
+            Some(path) => {
+                let (mut w, resume_after) = resume_output(path)?;
+                stream(bank, &mut w, resume_after)?.0
+            }
+            None => {
+                let mut w =
+                    ::csv::Writer::from_writer(HashingWriter::new(output(args.output.as_deref())?));
+                let (stats, rows) = stream(bank, &mut w, None)?;
+                if let Some(path) = &args.manifest {
+                    let output = w.into_inner().map_err(|error| error.into_error())?;
+                    output.manifest(rows).write(path)?;
+                }
+                stats
+            }
+        };
+        if args.print_processed {
+            eprintln!(
+                "processed {} rows, skipped {}",
+                stats.processed, stats.skipped
+            );
+        }
+        if args.stats {
+            print_stats(&stats);
+        }
+        return Ok(());
+    }
 
-    // todo: this is probably not great for large datasets with around 2MB of account data
-    println!("{}", bank.ordered_accounts_balance_buffer()?);
+    let processed = match &args.events {
+        Some(path) => {
+            let mut w = ::csv::Writer::from_path(path)?;
+            let mut written = Ok(());
+            let processed = bank.process_with_events(|event| {
+                if written.is_ok() {
+                    written = w.serialize(event);
+                }
+            });
+            written?;
+            w.flush()?;
+            processed
+        }
+        None if args.progress => {
+            bank.process_with_progress(PROGRESS_INTERVAL, |progress| match progress.percent() {
+                Some(percent) => {
+                    tracing::info!(processed = progress.processed, "{:.1}% processed", percent)
+                }
+                None => tracing::info!(processed = progress.processed, bytes = progress.bytes),
+            })
+        }
+        None => bank.process(),
+    };
+    match processed {
+        // a missed deadline still emits the accounts processed so far
+        Ok(_) | Err(HeathError::Cancelled { .. }) => {}
+        Err(error) => return Err(error.into()),
+    }
+    if args.print_processed {
+        let stats = bank.stats();
+        eprintln!(
+            "processed {} rows, skipped {}",
+            stats.processed, stats.skipped
+        );
+    }
+
+    let mut output = HashingWriter::new(output(args.output.as_deref())?);
+    let rows = match &args.layout {
+        Some(layout) if args.output_format == "fixed-width" => {
+            let accounts = bank.ordered_accounts()?;
+            for account in &accounts {
+                writeln!(output, "{}", layout.record(account)?)?;
+            }
+            output.flush()?;
+            accounts.len()
+        }
+        _ => bank.write_accounts_flushing(&mut output, args.flush_every)?,
+    };
+    if let Some(path) = &args.manifest {
+        output.manifest(rows).write(path)?;
+    }
+    if args.batch_summaries {
+        for batch in bank.batch_summaries() {
+            eprintln!(
+                "batch {}: {} rows, net {}",
+                batch.label,
+                batch.rows,
+                batch.net.normalize()
+            );
+        }
+    }
+    if args.bank_total {
+        eprintln!("bank total: {}", bank.total_funds()?.normalize());
+    }
+    if args.global_reconcile {
+        let reconcile = bank.reconcile_global()?;
+        eprintln!(
+            "global reconcile: expected {}, actual {}",
+            reconcile.expected().normalize(),
+            reconcile.actual.normalize()
+        );
+        if !reconcile.matches() {
+            eprintln!("warning: bank-wide total funds do not match the expected funds");
+        }
+    }
+    if args.report_anomalies {
+        for anomaly in bank.anomalies()? {
+            eprintln!("anomaly: {}", anomaly);
+        }
+    }
+    if args.report_orphans {
+        eprintln!(
+            "orphan accounts: {}",
+            bank.orphan_accounts()?.iter().join(",")
+        );
+    }
+    if args.report_locked {
+        eprintln!(
+            "locked accounts: {}",
+            bank.locked_clients()?.iter().join(",")
+        );
+    }
+    if args.stats {
+        print_stats(&bank.stats());
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.output_sqlite {
+        let mut connection = rusqlite::Connection::open(path)?;
+        ledger::sqlite::write_accounts(&mut connection, &bank.ordered_accounts()?)?;
+    }
+    if let Some(path) = &args.error_log {
+        let mut w = ::csv::Writer::from_path(path)?;
+        for entry in bank.error_log() {
+            w.serialize(entry)?;
+        }
+        w.flush()?;
+    }
+    if let Some(path) = &args.dump_index {
+        let mut w = ::csv::Writer::from_path(path)?;
+        for transaction in bank.transaction_index() {
+            w.serialize(TransactionLogCsv::from(transaction))?;
+        }
+        w.flush()?;
+    }
+    if let Some(path) = &args.dispute_report {
+        let mut w = ::csv::Writer::from_path(path)?;
+        for entry in bank.dispute_report()? {
+            w.serialize(entry)?;
+        }
+        w.flush()?;
+    }
+
+    processed?;
+    Ok(())
+}
+
+/// Open the accounts output: the given file, buffered, or stdout if none is given.
+fn output(path: Option<&Path>) -> anyhow::Result<Box<dyn Write>> {
+    match path {
+        Some(path) => {
+            let file = std::fs::File::create(path).map_err(|error| {
+                anyhow::anyhow!("Failed to create the output {}: {}", path.display(), error)
+            })?;
+            Ok(Box::new(BufWriter::new(file)))
+        }
+        None => Ok(Box::new(std::io::stdout())),
+    }
+}
+
+/// Stream out each client's account into the writer, skipping the clients up to and including
+/// `resume_after`. Returns the processing stats and the number of accounts written.
+fn stream<S: AccountStore, W: Write>(
+    bank: &mut Bank<S>,
+    w: &mut ::csv::Writer<W>,
+    resume_after: Option<ClientId>,
+) -> anyhow::Result<(ProcessStats, usize)> {
+    let mut rows = 0;
+    let stats = bank.process_sorted_by_client(resume_after, |account| {
+        rows += 1;
+        Ok(w.serialize(account)?)
+    })?;
+    w.flush()?;
+    Ok((stats, rows))
+}
+
+/// Report the problems found in the ledger, failing if there are any.
+fn check(ledger: &Ledger, max_problems: Option<usize>) -> anyhow::Result<()> {
+    let check = ledger.check(max_problems)?;
+    for problem in &check.problems {
+        println!("{}", problem);
+    }
+    if check.stopped_early {
+        println!(
+            "stopped after {} problems, there may be more",
+            check.problems.len()
+        );
+    }
+    match check.problems.len() {
+        0 => Ok(()),
+        problems => Err(anyhow::anyhow!("Found {} problems in the ledger", problems)),
+    }
+}
 
+/// Process the bank and print the statement of its statement client.
+fn statement(mut bank: Bank) -> anyhow::Result<()> {
+    bank.process()?;
+    let mut w = ::csv::Writer::from_writer(std::io::stdout());
+    for line in bank.statement() {
+        w.serialize(line)?;
+    }
+    w.flush()?;
     Ok(())
 }
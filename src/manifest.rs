@@ -0,0 +1,92 @@
+use crate::error::HeathResult;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{io::Write, path::Path};
+
+/// Manifest of the output, for integrity verification across systems
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Manifest {
+    /// Hex encoded SHA-256 of the output bytes.
+    pub sha256: String,
+    /// Number of account rows in the output, excluding any headers.
+    pub rows: usize,
+    /// Version of the tool which wrote the output.
+    pub version: &'static str,
+}
+impl Manifest {
+    /// Write the manifest as JSON to the given path.
+    pub fn write(&self, path: &Path) -> HeathResult<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+/// A writer which hashes the bytes as they're written through it
+#[derive(Debug)]
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+    /// Get the manifest of the bytes written so far, which contain the given number of rows.
+    pub fn manifest(self, rows: usize) -> Manifest {
+        Manifest {
+            sha256: hex(&self.hasher.finalize()),
+            rows,
+            version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Lowercase hex encoding of the bytes.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hex, HashingWriter};
+    use crate::bank::tests::bank;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn manifest() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/dispute/ok");
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+        let accounts = bank.ordered_accounts()?;
+
+        let output = tempfile::NamedTempFile::new()?;
+        let mut w = csv::Writer::from_writer(HashingWriter::new(output.reopen()?));
+        for account in &accounts {
+            w.serialize(account)?;
+        }
+        let manifest = w
+            .into_inner()
+            .map_err(|error| error.into_error())?
+            .manifest(accounts.len());
+
+        let independent = hex(&Sha256::digest(std::fs::read(output.path())?));
+        assert_eq!(manifest.sha256, independent);
+        assert_eq!(manifest.rows, accounts.len());
+        assert_eq!(manifest.version, env!("CARGO_PKG_VERSION"));
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use crate::{account::AccountInfo, csv::account::AccountLog, error::HeathResult};
+
+/// Write the `accounts` into the sqlite `accounts` table, replacing any existing rows.
+/// Decimals are stored as text so that no precision is lost.
+pub fn write_accounts(
+    connection: &mut rusqlite::Connection,
+    accounts: &[AccountLog],
+) -> HeathResult<()> {
+    let transaction = connection.transaction()?;
+    transaction.execute_batch(
+        "CREATE TABLE IF NOT EXISTS accounts (
+            client INTEGER PRIMARY KEY,
+            available TEXT NOT NULL,
+            held TEXT NOT NULL,
+            total TEXT NOT NULL,
+            locked INTEGER NOT NULL
+        );
+        DELETE FROM accounts;",
+    )?;
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO accounts (client, available, held, total, locked)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for account in accounts {
+            insert.execute(rusqlite::params![
+                account.client_id(),
+                account.available_funds().to_string(),
+                account.held_funds().to_string(),
+                account.total_funds().to_string(),
+                account.locked(),
+            ])?;
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::tests::bank;
+
+    #[test]
+    fn write_accounts() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/dispute/report");
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        let expected = bank.ordered_accounts_balance_buffer()?;
+
+        let mut connection = rusqlite::Connection::open_in_memory()?;
+        super::write_accounts(&mut connection, &bank.ordered_accounts()?)?;
+
+        let mut select = connection.prepare(
+            "SELECT client, available, held, total, locked FROM accounts ORDER BY client",
+        )?;
+        let rows = select
+            .query_map([], |row| {
+                Ok(format!(
+                    "{},{},{},{},{}",
+                    row.get::<_, u16>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, bool>(4)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            expected.lines().skip(1).collect::<Vec<_>>(),
+            rows.iter().map(String::as_str).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{
+    account::{Account, AccountId, AccountInfo},
+    error::HeathResult,
+};
+use std::collections::HashMap;
+
+/// Storage of the bank accounts
+pub trait AccountStore: std::fmt::Debug {
+    /// Get a copy of the account with the given account_id, if it exists.
+    fn get(&self, account_id: AccountId) -> HeathResult<Option<Account>>;
+    /// Insert the account, replacing any existing account with the same id.
+    fn insert(&mut self, account: Account) -> HeathResult<()>;
+    /// Remove the account with the given account_id, returning it if it existed.
+    fn remove(&mut self, account_id: AccountId) -> HeathResult<Option<Account>>;
+    /// Iterate over all the stored accounts, in no particular order.
+    fn iter(&self) -> Box<dyn Iterator<Item = HeathResult<Account>> + '_>;
+    /// Remove all the stored accounts.
+    fn clear(&mut self) -> HeathResult<()>;
+}
+
+/// The default, in-memory, account store
+impl AccountStore for HashMap<AccountId, Account> {
+    fn get(&self, account_id: AccountId) -> HeathResult<Option<Account>> {
+        Ok(HashMap::get(self, &account_id).cloned())
+    }
+    fn insert(&mut self, account: Account) -> HeathResult<()> {
+        HashMap::insert(self, account.client_id(), account);
+        Ok(())
+    }
+    fn remove(&mut self, account_id: AccountId) -> HeathResult<Option<Account>> {
+        Ok(HashMap::remove(self, &account_id))
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = HeathResult<Account>> + '_> {
+        Box::new(self.values().cloned().map(Ok))
+    }
+    fn clear(&mut self) -> HeathResult<()> {
+        HashMap::clear(self);
+        Ok(())
+    }
+}
+
+/// An on-disk account store, for when the accounts do not fit in memory
+#[cfg(feature = "sled")]
+#[derive(Debug)]
+pub struct SledStore {
+    db: sled::Db,
+}
+
+#[cfg(feature = "sled")]
+impl SledStore {
+    /// Open, or create, the store at the given path
+    pub fn open(path: &std::path::Path) -> HeathResult<Self> {
+        Ok(Self {
+            db: sled::open(path)?,
+        })
+    }
+    /// Create a temporary store which is removed when dropped
+    pub fn temporary() -> HeathResult<Self> {
+        Ok(Self {
+            db: sled::Config::new().temporary(true).open()?,
+        })
+    }
+}
+
+#[cfg(feature = "sled")]
+impl AccountStore for SledStore {
+    fn get(&self, account_id: AccountId) -> HeathResult<Option<Account>> {
+        match self.db.get(account_id.to_be_bytes())? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+    fn insert(&mut self, account: Account) -> HeathResult<()> {
+        self.db.insert(
+            account.client_id().to_be_bytes(),
+            serde_json::to_vec(&account)?,
+        )?;
+        Ok(())
+    }
+    fn remove(&mut self, account_id: AccountId) -> HeathResult<Option<Account>> {
+        match self.db.remove(account_id.to_be_bytes())? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = HeathResult<Account>> + '_> {
+        Box::new(
+            self.db
+                .iter()
+                .values()
+                .map(|value| Ok(serde_json::from_slice(&value?)?)),
+        )
+    }
+    fn clear(&mut self) -> HeathResult<()> {
+        self.db.clear()?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "sled"))]
+mod tests {
+    use super::SledStore;
+    use crate::{
+        config::{BankConfig, LedgerConfig},
+        error::HeathError,
+        Bank, Ledger,
+    };
+
+    #[test]
+    fn sled_store() -> anyhow::Result<()> {
+        let mut inputs = vec![];
+        for kind in std::fs::read_dir("./test_data")? {
+            let kind = kind?.path();
+            if !kind.is_dir() {
+                continue;
+            }
+            for case in std::fs::read_dir(kind)? {
+                let input = case?.path().join("input.csv");
+                if input.is_file() {
+                    inputs.push(input);
+                }
+            }
+        }
+        assert!(!inputs.is_empty());
+
+        for input in inputs {
+            let ledger = Ledger::from_path(input.clone(), LedgerConfig::default())?;
+            // unparsable inputs are rejected regardless of the store
+            let check = ledger.check(None)?;
+            if check
+                .problems
+                .iter()
+                .any(|problem| matches!(problem, HeathError::Parse { .. }))
+            {
+                continue;
+            }
+            let expected =
+                Bank::new(ledger, BankConfig::default()).ordered_accounts_balance_buffer()?;
+
+            let ledger = Ledger::from_path(input.clone(), LedgerConfig::default())?;
+            let store = SledStore::temporary()?;
+            let actual = Bank::with_store(ledger, BankConfig::default(), store)
+                .ordered_accounts_balance_buffer()?;
+            assert_eq!(expected, actual, "{}", input.display());
+        }
+        Ok(())
+    }
+}
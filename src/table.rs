@@ -0,0 +1,140 @@
+use crate::{account::AccountInfo, config::BoolFormat, csv::account::AccountLog};
+use std::io::Write;
+
+/// Headers of the table columns.
+const HEADERS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// Write the accounts as an aligned ASCII table, for terminals, eg:
+/// +--------+-----------+--------+--------+--------+
+/// | client | available |   held |  total | locked |
+/// +--------+-----------+--------+--------+--------+
+/// |      1 |    3.0000 | 0.0000 | 3.0000 | false  |
+/// +--------+-----------+--------+--------+--------+
+/// The columns are as wide as their widest value, with the client and amounts right-aligned and
+/// the amounts padded to exactly `decimals` decimal places so that they line up.
+pub(crate) fn write_table<W: Write>(
+    accounts: &[AccountLog],
+    decimals: u32,
+    bool_format: BoolFormat,
+    mut w: W,
+) -> std::io::Result<()> {
+    let amount = |mut amount: rust_decimal::Decimal| {
+        amount.rescale(decimals);
+        amount.to_string()
+    };
+    let rows = accounts
+        .iter()
+        .map(|account| {
+            // the total adds up the rounded funds, so that the columns still add up
+            let available = account.available_funds().round_dp(decimals);
+            let held = account.held_funds().round_dp(decimals);
+            [
+                account.client_id().to_string(),
+                amount(available),
+                amount(held),
+                amount(available + held),
+                locked(account.locked(), bool_format).to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let mut widths = HEADERS.map(str::len);
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.len());
+        }
+    }
+
+    let separator = widths
+        .iter()
+        .map(|width| "-".repeat(width + 2))
+        .collect::<Vec<_>>()
+        .join("+");
+    let separator = format!("+{}+", separator);
+    let line = |values: &[&str]| {
+        let cells = values
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(column, (value, width))| match column {
+                // the locked column is text, the others are numbers
+                4 => format!(" {:<width$} ", value, width = width),
+                _ => format!(" {:>width$} ", value, width = width),
+            })
+            .collect::<Vec<_>>();
+        format!("|{}|", cells.join("|"))
+    };
+
+    writeln!(w, "{}", separator)?;
+    writeln!(w, "{}", line(&HEADERS))?;
+    writeln!(w, "{}", separator)?;
+    for row in &rows {
+        writeln!(w, "{}", line(&row.each_ref().map(String::as_str)))?;
+    }
+    writeln!(w, "{}", separator)
+}
+
+/// The `locked` value as represented by the `BoolFormat`.
+fn locked(value: bool, format: BoolFormat) -> &'static str {
+    match (format, value) {
+        (BoolFormat::TrueFalse, true) => "true",
+        (BoolFormat::TrueFalse, false) => "false",
+        (BoolFormat::OneZero, true) => "1",
+        (BoolFormat::OneZero, false) => "0",
+        (BoolFormat::YesNo, true) => "Y",
+        (BoolFormat::YesNo, false) => "N",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bank::tests::bank_with,
+        config::{BankConfig, LedgerConfig, OutputFormat},
+    };
+
+    #[test]
+    fn table() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/table/ok");
+        let config = BankConfig {
+            output_format: OutputFormat::Table,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        let table = bank.ordered_accounts_balance_buffer()?;
+        let expected = std::fs::read_to_string(test_folder.join("output.txt"))?;
+        assert_eq!(table, expected);
+
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[1],
+            "| client | available |    held |   total | locked |"
+        );
+        assert_eq!(
+            lines[3],
+            "|      1 |    1.5000 |  0.0000 |  1.5000 | false  |"
+        );
+        assert!(lines.iter().all(|line| line.len() == lines[0].len()));
+        Ok(())
+    }
+
+    #[test]
+    fn rounded_total() -> anyhow::Result<()> {
+        use crate::{config::BoolFormat, csv::account::AccountLog};
+        use rust_decimal_macros::dec;
+
+        let account = AccountLog::new(1, dec!(0.015), dec!(0.015), dec!(0.03), false);
+        let mut table = vec![];
+        super::write_table(&[account], 2, BoolFormat::TrueFalse, &mut table)?;
+        let table = String::from_utf8(table)?;
+        // the total adds up the rounded funds, rather than rounding 0.03 on its own
+        assert_eq!(
+            table.lines().nth(3),
+            Some("|      1 |      0.02 | 0.02 |  0.04 | false  |")
+        );
+        Ok(())
+    }
+}
@@ -4,6 +4,7 @@ use crate::{
     transactions::TransactionInfo,
 };
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 
 impl TransactionInfo for TransactionLog {
     fn transaction_type(&self) -> TransactionType {
@@ -13,41 +14,61 @@ impl TransactionInfo for TransactionLog {
             Self::Dispute { .. } => TransactionType::Dispute,
             Self::Resolve { .. } => TransactionType::Resolve,
             Self::Chargeback { .. } => TransactionType::Chargeback,
+            Self::Adjust { .. } => TransactionType::Adjust,
+            Self::Unlock { .. } => TransactionType::Unlock,
+            Self::Transfer { .. } => TransactionType::Transfer,
+            Self::Freeze { .. } => TransactionType::Freeze,
+            Self::Unfreeze { .. } => TransactionType::Unfreeze,
         }
     }
     fn client_id(&self) -> ClientId {
         match self {
             Self::Deposit { common, .. } => common.client_id,
             Self::Withdrawal { common, .. } => common.client_id,
-            Self::Dispute { common } => common.client_id,
+            Self::Dispute { common, .. } => common.client_id,
             Self::Resolve { common } => common.client_id,
             Self::Chargeback { common } => common.client_id,
+            Self::Adjust { common, .. } => common.client_id,
+            Self::Unlock { common } => common.client_id,
+            Self::Transfer { common, .. } => common.client_id,
+            Self::Freeze { common } => common.client_id,
+            Self::Unfreeze { common } => common.client_id,
         }
     }
     fn transaction_id(&self) -> TransactionId {
         match self {
             Self::Deposit { common, .. } => common.tx_id,
             Self::Withdrawal { common, .. } => common.tx_id,
-            Self::Dispute { common } => common.tx_id,
+            Self::Dispute { common, .. } => common.tx_id,
             Self::Resolve { common } => common.tx_id,
             Self::Chargeback { common } => common.tx_id,
+            Self::Adjust { common, .. } => common.tx_id,
+            Self::Unlock { common } => common.tx_id,
+            Self::Transfer { common, .. } => common.tx_id,
+            Self::Freeze { common } => common.tx_id,
+            Self::Unfreeze { common } => common.tx_id,
         }
     }
     fn amount(&self) -> Option<rust_decimal::Decimal> {
         match self {
             Self::Deposit { amount, .. } => Some(*amount),
             Self::Withdrawal { amount, .. } => Some(*amount),
-            Self::Dispute { .. } => None,
+            Self::Dispute { amount, .. } => *amount,
             Self::Resolve { .. } => None,
             Self::Chargeback { .. } => None,
+            Self::Adjust { amount, .. } => Some(*amount),
+            Self::Unlock { .. } => None,
+            Self::Transfer { amount, .. } => Some(*amount),
+            Self::Freeze { .. } => None,
+            Self::Unfreeze { .. } => None,
         }
     }
 }
 
 // https://github.com/BurntSushi/rust-csv/issues/211
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-pub(crate) enum TransactionLog {
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TransactionLog {
     Deposit {
         #[serde(flatten)]
         common: TransactionLogCommon,
@@ -57,6 +78,7 @@ pub(crate) enum TransactionLog {
         amount: rust_decimal::Decimal,
     },
     Withdrawal {
+        #[serde(flatten)]
         common: TransactionLogCommon,
         /// Transaction amount with a precision of up to four places past the
         /// rust_decimal::Decimal.
@@ -66,6 +88,12 @@ pub(crate) enum TransactionLog {
     Dispute {
         #[serde(flatten)]
         common: TransactionLogCommon,
+        /// Percentage of the disputed transaction amount to hold, the whole amount if not given.
+        #[serde(rename = "percent")]
+        percent: Option<rust_decimal::Decimal>,
+        /// Part of the disputed transaction amount to hold, the whole amount if not given.
+        #[serde(rename = "amount")]
+        amount: Option<rust_decimal::Decimal>,
     },
     Resolve {
         #[serde(flatten)]
@@ -75,31 +103,100 @@ pub(crate) enum TransactionLog {
         #[serde(flatten)]
         common: TransactionLogCommon,
     },
+    /// Administrative correction of the available funds.
+    Adjust {
+        #[serde(flatten)]
+        common: TransactionLogCommon,
+        /// Signed delta applied to the available funds.
+        #[serde(rename = "amount")]
+        amount: rust_decimal::Decimal,
+    },
+    /// Administrative reinstatement of an account locked by a chargeback.
+    Unlock {
+        #[serde(flatten)]
+        common: TransactionLogCommon,
+    },
+    /// Move of funds from the client's account into the destination client's account.
+    Transfer {
+        #[serde(flatten)]
+        common: TransactionLogCommon,
+        /// Client ID of the destination account.
+        #[serde(rename = "dest")]
+        dest: ClientId,
+        /// Transaction amount with a precision of up to four places past the
+        /// rust_decimal::Decimal.
+        #[serde(rename = "amount")]
+        amount: rust_decimal::Decimal,
+    },
+    /// Administrative hold of an account, locking it.
+    Freeze {
+        #[serde(flatten)]
+        common: TransactionLogCommon,
+    },
+    /// Administrative lift of an account hold, unlocking it.
+    Unfreeze {
+        #[serde(flatten)]
+        common: TransactionLogCommon,
+    },
 }
 
 /// Dispute state of a transaction
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) enum DisputeSate {
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum DisputeSate {
+    #[default]
     Undisputed,
     /// Currently being disputed.
     Disputed(rust_decimal::Decimal),
     /// Disputed and charged back.
     Chargeback,
+    /// Disputed and resolved, for good, as re-disputing it is not allowed.
+    Resolved,
 }
-impl Default for DisputeSate {
-    fn default() -> Self {
-        Self::Undisputed
-    }
+
+/// Dispute lifecycle outcome of a transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisputeOutcome {
+    /// Never disputed.
+    Undisputed,
+    /// Currently being disputed.
+    Disputed,
+    /// Disputed and then resolved.
+    Resolved,
+    /// Disputed and charged back.
+    Chargeback,
+}
+
+/// State of a transaction after processing the ledger
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransactionState {
+    /// The transaction is a processed deposit or withdrawal.
+    pub applied: bool,
+    /// The dispute lifecycle outcome of the transaction.
+    pub dispute: DisputeOutcome,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) struct TransactionLogCommon {
+pub struct TransactionLogCommon {
     /// Client ID.
     #[serde(rename = "client")]
     client_id: ClientId,
     /// Transaction ID.
     #[serde(rename = "tx")]
     tx_id: TransactionId,
+    /// Line of the transaction in the ledger, if read from one.
+    #[serde(skip)]
+    line: u64,
+}
+impl TransactionLogCommon {
+    /// Return a new `Self` for the given client and transaction, eg: to build the transactions
+    /// of a bank in memory rather than reading them from a ledger.
+    pub fn new(client_id: ClientId, tx_id: TransactionId) -> Self {
+        Self {
+            client_id,
+            tx_id,
+            line: 0,
+        }
+    }
 }
 // impl TransactionLogCommon {
 //     pub(crate) fn client_id(&self) -> ClientId {
@@ -110,29 +207,141 @@ pub(crate) struct TransactionLogCommon {
 //     }
 // }
 
-impl From<TransactionLogCsv> for TransactionLog {
-    fn from(tx: TransactionLogCsv) -> Self {
+impl TryFrom<TransactionLogCsv> for TransactionLog {
+    /// The reason why the csv row is not a valid transaction, eg: a deposit without an amount.
+    type Error = String;
+
+    fn try_from(tx: TransactionLogCsv) -> Result<Self, Self::Error> {
+        tx.validate()?;
+        let missing =
+            |column: &str| format!("{:?} is missing the {}", tx.transaction_type(), column);
         let common = TransactionLogCommon {
             client_id: tx.client_id(),
-            tx_id: tx.transaction_id(),
+            tx_id: match tx.transaction_type() {
+                TransactionType::Adjust
+                | TransactionType::Unlock
+                | TransactionType::Freeze
+                | TransactionType::Unfreeze => tx.transaction_id(),
+                _ => tx.optional_transaction_id().ok_or_else(|| missing("tx"))?,
+            },
+            line: 0,
         };
-        match tx.transaction_type() {
+        Ok(match tx.transaction_type() {
             TransactionType::Deposit => Self::Deposit {
                 common,
-                amount: tx.amount().expect("Deposit should contain the amount"),
+                amount: tx.amount().ok_or_else(|| missing("amount"))?,
             },
             TransactionType::Withdrawal => Self::Withdrawal {
                 common,
-                amount: tx.amount().expect("Withdrawal should contain the amount"),
+                amount: tx.amount().ok_or_else(|| missing("amount"))?,
+            },
+            TransactionType::Dispute => Self::Dispute {
+                common,
+                percent: tx.percent(),
+                amount: tx.amount(),
             },
-            TransactionType::Dispute => Self::Dispute { common },
             TransactionType::Resolve => Self::Resolve { common },
             TransactionType::Chargeback => Self::Chargeback { common },
-        }
+            TransactionType::Adjust => Self::Adjust {
+                common,
+                amount: tx.amount().ok_or_else(|| missing("amount"))?,
+            },
+            TransactionType::Unlock => Self::Unlock { common },
+            TransactionType::Freeze => Self::Freeze { common },
+            TransactionType::Unfreeze => Self::Unfreeze { common },
+            TransactionType::Transfer => Self::Transfer {
+                common,
+                dest: tx.dest().ok_or_else(|| missing("dest"))?,
+                amount: tx.amount().ok_or_else(|| missing("amount"))?,
+            },
+        })
+    }
+}
+
+impl From<&TransactionLog> for TransactionLogCsv {
+    fn from(tx: &TransactionLog) -> Self {
+        TransactionLogCsv::new(
+            tx.transaction_type(),
+            tx.client_id(),
+            tx.transaction_id(),
+            tx.amount(),
+        )
     }
 }
 
 impl TransactionLog {
+    fn common(&self) -> &TransactionLogCommon {
+        match self {
+            Self::Deposit { common, .. }
+            | Self::Withdrawal { common, .. }
+            | Self::Dispute { common, .. }
+            | Self::Resolve { common }
+            | Self::Chargeback { common }
+            | Self::Adjust { common, .. }
+            | Self::Unlock { common }
+            | Self::Transfer { common, .. }
+            | Self::Freeze { common }
+            | Self::Unfreeze { common } => common,
+        }
+    }
+    fn common_mut(&mut self) -> &mut TransactionLogCommon {
+        match self {
+            Self::Deposit { common, .. }
+            | Self::Withdrawal { common, .. }
+            | Self::Dispute { common, .. }
+            | Self::Resolve { common }
+            | Self::Chargeback { common }
+            | Self::Adjust { common, .. }
+            | Self::Unlock { common }
+            | Self::Transfer { common, .. }
+            | Self::Freeze { common }
+            | Self::Unfreeze { common } => common,
+        }
+    }
+    /// Line of the transaction in the ledger, or 0 if it was not read from one.
+    pub(crate) fn line(&self) -> u64 {
+        self.common().line
+    }
+    /// Set the line of the transaction in the ledger.
+    pub(crate) fn set_line(&mut self, line: u64) {
+        self.common_mut().line = line;
+    }
+    /// Divide the transaction amount, if any, by 10^scale.
+    /// Used when the input amounts are given as integer minor units.
+    pub(crate) fn scale_amount(&mut self, scale: u32) {
+        match self {
+            Self::Deposit { amount, .. }
+            | Self::Withdrawal { amount, .. }
+            | Self::Adjust { amount, .. }
+            | Self::Transfer { amount, .. } => {
+                *amount *= rust_decimal::Decimal::new(1, scale);
+            }
+            Self::Dispute { amount, .. } => {
+                if let Some(amount) = amount {
+                    *amount *= rust_decimal::Decimal::new(1, scale);
+                }
+            }
+            Self::Resolve { .. }
+            | Self::Chargeback { .. }
+            | Self::Unlock { .. }
+            | Self::Freeze { .. }
+            | Self::Unfreeze { .. } => {}
+        }
+    }
+    /// Merge the amount of a duplicate deposit into this deposit, if both are deposits.
+    /// Returns `None`, leaving this deposit as is, if the merged amount overflows.
+    pub(crate) fn merge_deposit(&mut self, duplicate: &TransactionLog) -> Option<()> {
+        if let (
+            Self::Deposit { amount, .. },
+            Self::Deposit {
+                amount: duplicate, ..
+            },
+        ) = (self, duplicate)
+        {
+            *amount = amount.checked_add(*duplicate)?;
+        }
+        Some(())
+    }
     #[allow(dead_code)]
     pub(crate) fn log_info(&self) {
         tracing::info!(type_=?self.transaction_type(), client=self.client_id(), tx=%self.transaction_id(), amount=?self.amount());
@@ -0,0 +1,69 @@
+use crate::{
+    account::{AccountInfo, SetAccountInfo},
+    error::HeathResult,
+    transactions::{checked_funds, LoggedAccount, Transaction},
+};
+
+/// An adjustment is an administrative correction which adds a signed delta to the client's
+/// available funds. It bypasses the deposit/withdrawal validation, meaning it applies to locked
+/// accounts and may push the available funds negative.
+/// An adjustment looks like
+/// type client tx amount
+/// adjust 5 +2.50
+#[derive(Debug)]
+pub(super) struct Adjust {
+    account: LoggedAccount,
+    delta: rust_decimal::Decimal,
+    skipped: Option<&'static str>,
+}
+
+impl Adjust {
+    pub(crate) fn new(account: LoggedAccount, delta: rust_decimal::Decimal) -> Self {
+        Self {
+            account,
+            delta,
+            skipped: None,
+        }
+    }
+}
+impl Transaction for Adjust {
+    #[tracing::instrument(err)]
+    fn execute(&mut self) -> HeathResult<()> {
+        let new_available = self.account.available_funds().checked_add(self.delta);
+        let new_available =
+            match checked_funds(&self.account, new_available, self.account.held_funds()) {
+                Some(new_available) => new_available,
+                None => {
+                    self.skipped = Some("adjust_overflow");
+                    return Ok(());
+                }
+            };
+        self.account.set_available_funds(new_available);
+        tracing::info!(category = "adjust", client = self.account.client_id(), delta = %self.delta, available = %new_available, "Account adjusted");
+        Ok(())
+    }
+    fn skipped(&self) -> Option<&'static str> {
+        self.skipped
+    }
+    fn flow(&self) -> rust_decimal::Decimal {
+        match self.skipped {
+            None => self.delta,
+            Some(_) => rust_decimal::Decimal::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bank::tests::test, init_tracing};
+
+    #[test]
+    fn ok() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/adjust/ok");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+}
@@ -1,8 +1,13 @@
 use crate::{
     account::{AccountInfo, SetAccountInfo},
-    bank::BankAccount,
+    config::FrozenBehavior,
+    csv::transaction::TransactionType,
+    error::HeathResult,
     transaction::{DisputeSate, TransactionLog},
-    transactions::{Transaction, TransactionInfo},
+    transactions::{
+        blocked_when_frozen, checked_funds, held_within, LoggedAccount, Transaction,
+        TransactionInfo,
+    },
 };
 
 /// A chargeback is the final state of a dispute and represents the client reversing a transaction.
@@ -16,51 +21,144 @@ use crate::{
 /// Like a dispute and a resolve a chargeback refers to the transaction by ID (tx) and does not
 /// specify an amount. Like a resolve, if the tx specified doesn't exist, or the tx isn't under
 /// dispute, you can ignore chargeback and assume this is an error on our partner's side.
+/// # Implicit Dispute:
+/// Some partners send chargebacks without a prior dispute. When enabled, a chargeback of an
+/// undisputed deposit implicitly disputes it and charges it back straight away.
+/// # Held Epsilon:
+/// A disputed amount exceeding the held funds by no more than the epsilon removes the held funds.
+/// # Frozen Account:
+/// A chargeback of a frozen account is allowed, unless blocked by the `FrozenBehavior`.
+/// # Disputed Withdrawal:
+/// The withdrawal is reversed, so its held funds are moved back into the available funds.
 #[derive(Debug)]
 pub(super) struct ChargeBack {
-    account: BankAccount,
+    account: LoggedAccount,
     disputed_tx: Option<TransactionLog>,
+    implicit_dispute: bool,
+    held_epsilon: rust_decimal::Decimal,
+    frozen: FrozenBehavior,
+    /// The funds removed from the bank, if charging back a deposit.
+    charged_back: rust_decimal::Decimal,
+    skipped: Option<&'static str>,
 }
 impl ChargeBack {
-    pub(crate) fn new(account: BankAccount, disputed_tx: Option<TransactionLog>) -> Self {
+    pub(crate) fn new(
+        account: LoggedAccount,
+        disputed_tx: Option<TransactionLog>,
+        implicit_dispute: bool,
+        held_epsilon: rust_decimal::Decimal,
+        frozen: FrozenBehavior,
+    ) -> Self {
         Self {
             account,
             disputed_tx,
+            implicit_dispute,
+            held_epsilon,
+            frozen,
+            charged_back: rust_decimal::Decimal::ZERO,
+            skipped: None,
         }
     }
 }
 impl Transaction for ChargeBack {
     #[tracing::instrument(err)]
-    fn execute(&mut self) -> anyhow::Result<()> {
+    fn execute(&mut self) -> HeathResult<()> {
+        if blocked_when_frozen(&self.account, self.frozen) {
+            self.skipped = Some("chargeback_account_frozen");
+            return Ok(());
+        }
         if let Some(dispute) = &self.disputed_tx {
             match self.account.find_dispute(dispute.transaction_id()) {
                 DisputeSate::Disputed(amount) => {
-                    assert!(
-                        amount <= self.account.held_funds(),
-                        "Amount held and disputes got out of sync - BUG"
-                    );
-                    self.account.remove_held_funds(dispute.transaction_id());
+                    let amount = match held_within(
+                        amount,
+                        self.account.held_funds(),
+                        self.held_epsilon,
+                    ) {
+                        Some(amount) => amount,
+                        None => {
+                            tracing::debug!(account=?self.account, disputed_tx=?dispute, "Amount held and disputes got out of sync");
+                            self.skipped = Some("chargeback_held_mismatch");
+                            return Ok(());
+                        }
+                    };
+                    if dispute.transaction_type() == TransactionType::Withdrawal {
+                        let held = self.account.held_funds() - amount;
+                        let available = self.account.available_funds().checked_add(amount);
+                        let available = match checked_funds(&self.account, available, held) {
+                            Some(available) => available,
+                            None => {
+                                self.skipped = Some("chargeback_overflow");
+                                return Ok(());
+                            }
+                        };
+                        // release the hold before crediting it, so the total is never inflated
+                        self.account.remove_held_funds(dispute.transaction_id());
+                        self.account.set_available_funds(available);
+                    } else {
+                        self.charged_back = amount;
+                        self.account.remove_held_funds(dispute.transaction_id());
+                    }
                     self.account
                         .complete_dispute(dispute.transaction_id(), DisputeSate::Chargeback);
 
                     // we're now frozen so we cannot issue any deposit/withdrawals?
                     self.account.set_locked(true);
                 }
-                DisputeSate::Undisputed => {
+                DisputeSate::Undisputed
+                    if self.implicit_dispute
+                        && dispute.transaction_type() == TransactionType::Deposit =>
+                {
+                    let amount = dispute.amount().unwrap_or_default();
+                    let available = self.account.available_funds();
+                    if available >= amount {
+                        let held = self.account.held_funds();
+                        match checked_funds(&self.account, available.checked_sub(amount), held) {
+                            Some(available) => self.account.set_available_funds(available),
+                            None => {
+                                self.skipped = Some("chargeback_overflow");
+                                return Ok(());
+                            }
+                        }
+                        self.account
+                            .complete_dispute(dispute.transaction_id(), DisputeSate::Chargeback);
+                        self.account.set_locked(true);
+                        self.charged_back = amount;
+                    } else {
+                        tracing::debug!(account=?self.account, disputed_tx=?dispute, "Implicitly disputed account does not have the funds!");
+                        self.skipped = Some("chargeback_insufficient_funds");
+                    }
+                }
+                DisputeSate::Undisputed | DisputeSate::Resolved => {
                     tracing::debug!(account=?self.account, disputed_tx=?dispute, "Transaction undisputed");
+                    self.skipped = Some("chargeback_not_disputed");
                 }
                 DisputeSate::Chargeback => {
                     tracing::debug!(account=?self.account, disputed_tx=?dispute, "Transaction has already been charged back");
+                    self.skipped = Some("chargeback_after_chargeback");
                 }
             }
+        } else {
+            tracing::debug!(account=?self.account, "Charged back Transaction not found.");
+            self.skipped = Some("chargeback_unknown_transaction");
         }
         Ok(())
     }
+    fn skipped(&self) -> Option<&'static str> {
+        self.skipped
+    }
+    fn flow(&self) -> rust_decimal::Decimal {
+        -self.charged_back
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{bank::tests::test, init_tracing};
+    use crate::{
+        bank::tests::{test, test_with},
+        config::BankConfig,
+        init_tracing,
+    };
 
     #[test]
     fn ok() -> anyhow::Result<()> {
@@ -72,6 +170,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn withdrawal_ok() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/chargeback/withdrawal_ok");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+
+        // the reversed withdrawal is no longer accounted as withdrawn
+        let mut bank = crate::bank::tests::bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+        let reconcile = bank.reconcile_global()?;
+        assert_eq!(reconcile.withdrawn, rust_decimal_macros::dec!(0));
+        assert!(reconcile.matches());
+
+        // the reversed withdrawal only restores the total funds, it never exceeds the deposit
+        let config = crate::config::BankConfig {
+            with_peak: true,
+            ..Default::default()
+        };
+        let mut bank = crate::bank::tests::bank_with(
+            test_folder.join("input.csv"),
+            Default::default(),
+            config,
+        )?;
+        assert_eq!(
+            bank.ordered_accounts_balance_buffer()?,
+            "client,available,held,total,locked,peak_total\n1,10,0,10,true,10\n"
+        );
+        Ok(())
+    }
+
     #[test]
     fn repeated() -> anyhow::Result<()> {
         init_tracing().ok();
@@ -111,4 +239,18 @@ mod tests {
         assert_eq!(expected, actual);
         Ok(())
     }
+
+    #[test]
+    fn implicit_dispute() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/chargeback/implicit_dispute");
+        let config = BankConfig {
+            implicit_dispute_on_chargeback: true,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
 }
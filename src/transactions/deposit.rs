@@ -1,7 +1,8 @@
 use crate::{
     account::{AccountInfo, SetAccountInfo},
-    bank::BankAccount,
-    transactions::{Transaction, TransactionError},
+    config::FrozenDepositPolicy,
+    error::HeathResult,
+    transactions::{checked_funds, LoggedAccount, Transaction, TransactionError},
 };
 
 /// A deposit is a credit to the client's asset account, meaning it should increase the available
@@ -9,39 +10,70 @@ use crate::{
 /// A deposit looks like
 /// type client tx amount
 /// deposit 1 1 1.0
-/// Withdrawal
-/// A withdraw is a debit to the client's asset account, meaning it should decrease the available
-/// and total funds of the client account
+/// A deposit to a frozen account is ignored, unless allowed by the `FrozenDepositPolicy`: a frozen
+/// account usually stops all movement of funds, but some institutions only stop the funds from
+/// leaving it, so that eg: a salary may still be received while the account is investigated.
 #[derive(Debug)]
 pub(super) struct Deposit {
-    account: BankAccount,
+    account: LoggedAccount,
     amount: rust_decimal::Decimal,
+    frozen: FrozenDepositPolicy,
+    skipped: Option<&'static str>,
 }
 
 impl Deposit {
-    pub(crate) fn new(account: BankAccount, amount: rust_decimal::Decimal) -> Self {
-        Self { account, amount }
+    pub(crate) fn new(
+        account: LoggedAccount,
+        amount: rust_decimal::Decimal,
+        frozen: FrozenDepositPolicy,
+    ) -> Self {
+        Self {
+            account,
+            amount,
+            frozen,
+            skipped: None,
+        }
     }
 }
 impl Transaction for Deposit {
     #[tracing::instrument(err)]
-    fn execute(&mut self) -> anyhow::Result<()> {
-        if !self.account.locked() {
-            let new_available = self.account.available_funds() + self.amount;
-            self.account.set_available_funds(new_available);
+    fn execute(&mut self) -> HeathResult<()> {
+        if !self.account.locked() || self.frozen == FrozenDepositPolicy::Allow {
+            let new_available = self.account.available_funds().checked_add(self.amount);
+            match checked_funds(&self.account, new_available, self.account.held_funds()) {
+                Some(new_available) => self.account.set_available_funds(new_available),
+                None => self.skipped = Some("deposit_overflow"),
+            }
         } else {
             let error = TransactionError::AccountFrozen {
                 account: self.account.client_id(),
             };
             tracing::debug!(error=%error, "non-fatal error occurred");
+            self.skipped = Some("deposit_account_frozen");
         }
         Ok(())
     }
+    fn skipped(&self) -> Option<&'static str> {
+        self.skipped
+    }
+    fn flow(&self) -> rust_decimal::Decimal {
+        match self.skipped {
+            None => self.amount,
+            Some(_) => rust_decimal::Decimal::ZERO,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{bank::tests::test, init_tracing};
+    use crate::{
+        bank::tests::{bank_with, test, test_with},
+        config::{
+            BankConfig, DuplicatePolicy, DuplicateScope, FrozenDepositPolicy, LedgerConfig,
+            Rounding,
+        },
+        init_tracing,
+    };
 
     #[test]
     fn ok() -> anyhow::Result<()> {
@@ -52,4 +84,201 @@ mod tests {
         assert_eq!(expected, actual);
         Ok(())
     }
+
+    #[test]
+    fn overflow() -> anyhow::Result<()> {
+        use crate::{bank::tests::bank_with, config::InputFormat};
+        init_tracing().ok();
+
+        // csv amounts this large are not parsed, so the ledger is given as json
+        let test_folder = std::path::Path::new("./test_data/deposit/overflow");
+        let ledger_config = LedgerConfig {
+            format: InputFormat::Json,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.json"),
+            ledger_config,
+            BankConfig::default(),
+        )?;
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(bank.ordered_accounts_balance_buffer()?, expected);
+        assert_eq!(bank.skipped("deposit_overflow"), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn frozen_allowed() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/deposit/frozen_allowed");
+        let config = BankConfig {
+            frozen_deposit_policy: FrozenDepositPolicy::Allow,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+
+        // by default the deposit to the frozen account is ignored
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("1,0,0,0,true"), "{}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_amount() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/deposit/missing_amount");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn negative() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/deposit/negative");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn client_out_of_range() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        // the deposits of client ids which do not fit a u16 are skipped
+        let test_folder = std::path::Path::new("./test_data/deposit/client_out_of_range");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn minor_units() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/deposit/minor_units");
+        let ledger_config = LedgerConfig {
+            amount_scale: 4,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, ledger_config.clone(), Default::default())?;
+        assert_eq!(expected, actual);
+
+        // the negative amounts are skipped, even once scaled below a minor unit, whereas the zero
+        // amounts are applied without moving any funds
+        let test_folder = std::path::Path::new("./test_data/deposit/minor_units_signed");
+        let (expected, actual) = test_with(test_folder, ledger_config.clone(), Default::default())?;
+        assert_eq!(expected, actual);
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            ledger_config,
+            Default::default(),
+        )?;
+        let stats = bank.process()?;
+        assert_eq!(stats.skipped_by_category.get("negative_amount"), Some(&2));
+        assert_eq!(stats.skipped, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn round_at_output_only() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/deposit/round_at_output_only");
+        let config = BankConfig {
+            rounding: Rounding::OutputOnly,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+
+        // rounding each tiny deposit loses all of them
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("1,0,0,0,false"), "{}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn merge_duplicates() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/deposit/merge_duplicates");
+        let config = BankConfig {
+            merge_duplicate_deposits: true,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+
+        // by default the dispute holds only the first deposit
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("1,3,5,8,false"), "{}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn merge_duplicates_overflow() {
+        use crate::{
+            transaction::{TransactionLog, TransactionLogCommon},
+            transactions::TransactionInfo,
+        };
+        use rust_decimal_macros::dec;
+
+        // the bank's funds overflow before any merged deposit does, so merge them directly
+        let deposit = |amount| TransactionLog::Deposit {
+            common: TransactionLogCommon::new(1, 1),
+            amount,
+        };
+        let mut merged = deposit(rust_decimal::Decimal::MAX);
+        assert_eq!(merged.merge_deposit(&deposit(dec!(1))), None);
+        assert_eq!(merged.amount(), Some(rust_decimal::Decimal::MAX));
+
+        let mut merged = deposit(dec!(5));
+        assert_eq!(merged.merge_deposit(&deposit(dec!(3))), Some(()));
+        assert_eq!(merged.amount(), Some(dec!(8)));
+    }
+
+    #[test]
+    fn ignore_duplicates() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/deposit/ignore_duplicates");
+        let config = BankConfig {
+            duplicate_policy: DuplicatePolicy::Ignore,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config.clone())?;
+        assert_eq!(expected, actual);
+
+        // the tx id of client 1 cannot be reused by client 2 either
+        let config = BankConfig {
+            duplicate_scope: DuplicateScope::Global,
+            ..config
+        };
+        let (_, actual) = test_with(test_folder, Default::default(), config)?;
+        assert!(!actual.contains("\n2,"), "{}", actual);
+
+        // by default the duplicate is applied
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("1,3,5,8,false"), "{}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn no_rounding() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/deposit/no_rounding");
+        let config = BankConfig {
+            rounding: Rounding::Never,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
 }
@@ -1,8 +1,13 @@
 use crate::{
     account::{AccountInfo, SetAccountInfo},
-    bank::BankAccount,
+    config::{DisputePolicy, FrozenBehavior, RepeatedDisputePolicy},
+    csv::transaction::{valid_percent, TransactionType},
+    error::HeathResult,
     transaction::{DisputeSate, TransactionLog},
-    transactions::{Transaction, TransactionInfo},
+    transactions::{
+        blocked_when_frozen, checked_funds, log_overflow, LoggedAccount, Transaction,
+        TransactionInfo,
+    },
 };
 
 /// A dispute represents a client's claim that a transaction was erroneous and should be reversed.
@@ -16,65 +21,208 @@ use crate::{
 /// Notice that a dispute does not state the amount disputed. Instead a dispute references the
 /// transaction that is disputed by ID. If the tx specified by the dispute doesn't exist you can
 /// ignore it and assume this is an error on our partners side
+/// # Partial Dispute:
+/// A dispute may carry a percent, in which case only that percentage of the disputed amount is
+/// held, eg: dispute 1 1 with percent 50 holds half of the amount of transaction 1.
+/// Likewise a dispute may carry an amount, in which case only that amount is held, eg: dispute 1 1
+/// 2.5 holds 2.5 of the amount of transaction 1. A dispute of more than the transaction's amount
+/// is ignored.
+/// # Insufficient Funds:
+/// A dispute of more than the available funds is handled as per the `DisputePolicy`, either
+/// ignored or holding the whole amount with the available funds going negative.
+/// # Repeated Dispute:
+/// A dispute of an already disputed transaction is handled as per the `RepeatedDisputePolicy`.
+/// # Frozen Account:
+/// A dispute of a frozen account is allowed, unless blocked by the `FrozenBehavior`.
+/// # Disputed Withdrawal:
+/// The funds of a disputed withdrawal have already left the account, so rather than moving them
+/// out of the available funds the disputed amount is credited into the held funds, increasing
+/// the total funds while the dispute is ongoing. A resolve releases the hold, the withdrawal
+/// standing, and a chargeback reverses the withdrawal, moving the held amount into the available
+/// funds.
 #[derive(Debug)]
 pub(super) struct Dispute {
-    account: BankAccount,
+    account: LoggedAccount,
     disputed_tx: Option<TransactionLog>,
+    percent: Option<rust_decimal::Decimal>,
+    amount: Option<rust_decimal::Decimal>,
+    policy: RepeatedDisputePolicy,
+    insufficient: DisputePolicy,
+    frozen: FrozenBehavior,
+    /// The withdrawn funds credited back into the held funds, if disputing a withdrawal.
+    credited: rust_decimal::Decimal,
+    skipped: Option<&'static str>,
 }
 impl Dispute {
-    pub(crate) fn new(account: BankAccount, disputed_tx: Option<TransactionLog>) -> Self {
+    pub(crate) fn new(
+        account: LoggedAccount,
+        disputed_tx: Option<TransactionLog>,
+        percent: Option<rust_decimal::Decimal>,
+        amount: Option<rust_decimal::Decimal>,
+        policy: RepeatedDisputePolicy,
+        insufficient: DisputePolicy,
+        frozen: FrozenBehavior,
+    ) -> Self {
         Self {
             account,
             disputed_tx,
+            percent,
+            amount,
+            policy,
+            insufficient,
+            frozen,
+            credited: rust_decimal::Decimal::ZERO,
+            skipped: None,
+        }
+    }
+    /// Hold the disputed amount, first releasing the `released` prior hold, if any.
+    /// Unless allowed by the `DisputePolicy` the dispute is ignored if the account does not have
+    /// the funds.
+    fn hold(&mut self, disputed_tx: &TransactionLog, released: Option<rust_decimal::Decimal>) {
+        let amount = match self.held_amount(disputed_tx) {
+            Ok(amount) => amount,
+            Err(category) => {
+                self.skipped = Some(category);
+                return;
+            }
+        };
+        // the holds stacked on a transaction may not exceed its amount
+        let amount = match self.account.find_dispute(disputed_tx.transaction_id()) {
+            DisputeSate::Disputed(prior) if released.is_none() => {
+                let remaining = disputed_tx.amount().unwrap_or_default() - prior;
+                if remaining <= rust_decimal::Decimal::ZERO {
+                    tracing::debug!(account=?self.account, disputed_tx=?disputed_tx, "Transaction amount is already held");
+                    self.skipped = Some("dispute_amount_exceeded");
+                    return;
+                }
+                amount.min(remaining)
+            }
+            _ => amount,
+        };
+        let held = self.account.held_funds() - released.unwrap_or_default();
+        if disputed_tx.transaction_type() == TransactionType::Withdrawal {
+            // the hold is credited on top of the available funds, so their total may overflow
+            let available = self.account.available_funds();
+            if checked_funds(&self.account, held.checked_add(amount), available).is_none() {
+                self.skipped = Some("dispute_overflow");
+                return;
+            }
+            // the withdrawn funds are no longer available, so the hold is credited instead
+            if released.is_some() {
+                self.account.remove_held_funds(disputed_tx.transaction_id());
+            }
+            self.account
+                .add_held_funds(amount, disputed_tx.transaction_id());
+            self.credited = amount - released.unwrap_or_default();
+            return;
+        }
+        let available = self.account.available_funds() + released.unwrap_or_default();
+        if available >= amount || self.insufficient == DisputePolicy::AllowNegative {
+            let new_available = held
+                .checked_add(amount)
+                .and_then(|held| checked_funds(&self.account, available.checked_sub(amount), held));
+            let new_available = match new_available {
+                Some(new_available) => new_available,
+                None => {
+                    self.skipped = Some("dispute_overflow");
+                    return;
+                }
+            };
+            if released.is_some() {
+                self.account.remove_held_funds(disputed_tx.transaction_id());
+            }
+            self.account.set_available_funds(new_available);
+            self.account
+                .add_held_funds(amount, disputed_tx.transaction_id());
+        } else {
+            tracing::debug!(account=?self.account, disputed_tx=?disputed_tx, "Disputed account does not have the funds!");
+            self.skipped = Some("dispute_insufficient_funds");
+        }
+    }
+    /// Get the amount held by disputing the `disputed_tx`, as per the dispute percent or amount.
+    /// An invalid percent, or an amount exceeding the transaction's, holds nothing, ie: the dispute
+    /// is ignored, with the category of the reason.
+    fn held_amount(
+        &self,
+        disputed_tx: &TransactionLog,
+    ) -> Result<rust_decimal::Decimal, &'static str> {
+        let amount = disputed_tx.amount().unwrap_or_default();
+        match (self.percent, self.amount) {
+            (_, Some(disputed)) if disputed > amount => {
+                tracing::debug!(disputed_tx=?disputed_tx, amount=%disputed, "Dispute amount exceeds the transaction amount");
+                Err("dispute_amount_exceeded")
+            }
+            (_, Some(disputed)) => Ok(disputed),
+            (None, None) => Ok(amount),
+            (Some(percent), None) if valid_percent(percent) => amount
+                .checked_mul(percent)
+                .and_then(|amount| amount.checked_div(rust_decimal::Decimal::ONE_HUNDRED))
+                .ok_or_else(|| {
+                    log_overflow(&self.account);
+                    "dispute_overflow"
+                }),
+            (Some(percent), None) => {
+                tracing::debug!(disputed_tx=?disputed_tx, percent=%percent, "Invalid dispute percent");
+                Err("dispute_invalid_percent")
+            }
         }
     }
 }
 impl Transaction for Dispute {
     #[tracing::instrument(err)]
-    fn execute(&mut self) -> anyhow::Result<()> {
-        // disputes for locked accounts are currently allowed
+    fn execute(&mut self) -> HeathResult<()> {
+        if blocked_when_frozen(&self.account, self.frozen) {
+            self.skipped = Some("dispute_account_frozen");
+            return Ok(());
+        }
         match &self.disputed_tx {
             None => {
                 tracing::debug!(account=?self.account, "Disputed Transaction not found.");
+                self.skipped = Some("dispute_unknown_transaction");
                 Ok(())
             }
             Some(disputed_tx) => {
-                // Check that we don't dispute the same account twice for the same transaction
+                let disputed_tx = disputed_tx.clone();
+                // Check whether the same account is disputed twice for the same transaction
                 let disputed_id = disputed_tx.transaction_id();
                 match self.account.find_dispute(disputed_id) {
-                    DisputeSate::Undisputed => {
-                        if let Some(amount) = disputed_tx.amount() {
-                            let available = self.account.available_funds();
-                            if available >= amount {
-                                let new_available = available - amount;
-                                self.account.set_available_funds(new_available);
-                                self.account
-                                    .add_held_funds(amount, disputed_tx.transaction_id());
-                            } else {
-                                // I did not find the correct procedure in the document so I'm
-                                // assuming that here we take the
-                                // same approach as a withdrawal? Or would we
-                                // allow the account funds to go negative?
-                                tracing::debug!(account=?self.account, disputed_tx=?disputed_tx, "Disputed account does not have the funds!");
-                            }
+                    DisputeSate::Undisputed => self.hold(&disputed_tx, None),
+                    DisputeSate::Disputed(held) => match self.policy {
+                        RepeatedDisputePolicy::Ignore => {
+                            tracing::debug!(account=?self.account, disputed_tx=?disputed_tx, "Transaction is already disputed");
+                            self.skipped = Some("dispute_already_disputed");
                         }
-                    }
-                    DisputeSate::Disputed(_) => {
-                        tracing::debug!(account=?self.account, disputed_tx=?disputed_tx, "Transaction is already disputed");
-                    }
+                        RepeatedDisputePolicy::Stack => self.hold(&disputed_tx, None),
+                        RepeatedDisputePolicy::Replace => self.hold(&disputed_tx, Some(held)),
+                    },
                     DisputeSate::Chargeback => {
                         tracing::debug!(account=?self.account, disputed_tx=?disputed_tx, "Transaction has already been charged back");
+                        self.skipped = Some("dispute_after_chargeback");
+                    }
+                    DisputeSate::Resolved => {
+                        tracing::debug!(account=?self.account, disputed_tx=?disputed_tx, "Transaction has already been resolved");
+                        self.skipped = Some("dispute_after_resolve");
                     }
                 }
                 Ok(())
             }
         }
     }
+    fn skipped(&self) -> Option<&'static str> {
+        self.skipped
+    }
+    fn flow(&self) -> rust_decimal::Decimal {
+        self.credited
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{bank::tests::test, init_tracing};
+    use crate::{
+        bank::tests::{bank, test, test_with},
+        config::BankConfig,
+        init_tracing,
+    };
 
     #[test]
     fn ok() -> anyhow::Result<()> {
@@ -86,6 +234,14 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn withdrawal_ok() -> anyhow::Result<()> {
+        let test_folder = std::path::Path::new("./test_data/dispute/withdrawal_ok");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
     #[test]
     fn repeated_unresolved() -> anyhow::Result<()> {
         init_tracing().ok();
@@ -126,6 +282,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn not_yet_applied() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        // the dispute of tx 2 precedes its deposit and so is ignored, as is its chargeback
+        let test_folder = std::path::Path::new("./test_data/dispute/not_yet_applied");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+        assert_eq!(bank.skipped("dispute_unknown_transaction"), 1);
+        assert_eq!(bank.skipped("chargeback_not_disputed"), 1);
+        Ok(())
+    }
+
     #[test]
     fn invalid_cid_tx() -> anyhow::Result<()> {
         init_tracing().ok();
@@ -135,4 +307,262 @@ mod tests {
         assert_eq!(expected, actual);
         Ok(())
     }
+
+    #[test]
+    fn wrong_client() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        // client 2 disputes, resolves and charges back tx 1 of client 1, which moves no funds
+        let test_folder = std::path::Path::new("./test_data/dispute/wrong_client");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+
+        let mut bank = crate::bank::tests::bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+        for category in [
+            "dispute_wrong_client",
+            "resolve_wrong_client",
+            "chargeback_wrong_client",
+        ] {
+            assert_eq!(bank.skipped(category), 1, "{}", category);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn max_disputes() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/dispute/max_disputes");
+        let config = BankConfig {
+            max_disputes: Some(2),
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn percent() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/dispute/percent");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn percent_overflow() -> anyhow::Result<()> {
+        use crate::{
+            bank::tests::bank_with,
+            config::{InputFormat, LedgerConfig},
+        };
+        init_tracing().ok();
+
+        // the percent of the deposit is computed within the `rust_decimal::Decimal` bounds, so the
+        // dispute is skipped rather than panicking
+        let test_folder = std::path::Path::new("./test_data/dispute/percent_overflow");
+        let ledger_config = LedgerConfig {
+            format: InputFormat::Json,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.json"),
+            ledger_config,
+            BankConfig::default(),
+        )?;
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(bank.ordered_accounts_balance_buffer()?, expected);
+        assert_eq!(bank.skipped("dispute_overflow"), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn partial() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        // the dispute of 6 exceeds the 5 of tx 2, so it's ignored
+        let test_folder = std::path::Path::new("./test_data/dispute/partial");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+
+        let mut bank = crate::bank::tests::bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+        assert_eq!(bank.skipped("dispute_amount_exceeded"), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn insufficient_allow_negative() -> anyhow::Result<()> {
+        use crate::config::DisputePolicy;
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/dispute/insufficient_allow_negative");
+        let config = BankConfig {
+            dispute_policy: DisputePolicy::AllowNegative,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+
+        // by default the dispute is skipped
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("1,2,0,2,false"), "{}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn omit_empty() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/dispute/omit_empty");
+        let config = BankConfig {
+            omit_empty: true,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+
+        // by default the dispute of the unknown client opens an empty account
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("2,0,0,0,false"), "{}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn redispute_blocked() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/dispute/redispute_blocked");
+        let config = BankConfig {
+            no_redispute: true,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+
+        // by default the resolved tx 1 is disputed again, and charged back
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("1,5,0,5,true"), "{}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn frozen_blocked() -> anyhow::Result<()> {
+        use crate::config::FrozenBehavior;
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/dispute/frozen_blocked");
+        let config = BankConfig {
+            frozen_behavior: FrozenBehavior::BlockAll,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+
+        // by default tx 2 is disputed and charged back even though the account is frozen
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("1,0,0,0,true"), "{}", actual);
+        assert!(actual.contains("2,0,3,3,false"), "{}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_dispute_policy() -> anyhow::Result<()> {
+        use crate::config::RepeatedDisputePolicy;
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/dispute/repeated_policy");
+        let actual = |policy| -> anyhow::Result<String> {
+            let config = BankConfig {
+                repeated_dispute_policy: policy,
+                ..Default::default()
+            };
+            Ok(test_with(test_folder, Default::default(), config)?.1)
+        };
+        let (expected, ignored) = test(test_folder)?;
+        assert_eq!(expected, ignored);
+        assert_eq!(expected, actual(RepeatedDisputePolicy::Ignore)?);
+
+        let stacked = actual(RepeatedDisputePolicy::Stack)?;
+        // the stacked holds are capped at the disputed amount, with nothing left to hold ignored
+        assert!(stacked.contains("1,5,10,15,false"), "{}", stacked);
+        assert!(stacked.contains("2,1,2,3,false"), "{}", stacked);
+        // stacking beyond the available funds is ignored
+        assert!(stacked.contains("3,0,1,1,false"), "{}", stacked);
+
+        let replaced = actual(RepeatedDisputePolicy::Replace)?;
+        assert!(replaced.contains("1,5,10,15,false"), "{}", replaced);
+        assert!(replaced.contains("2,1,2,3,false"), "{}", replaced);
+        assert!(replaced.contains("3,0,1,1,false"), "{}", replaced);
+        Ok(())
+    }
+
+    #[test]
+    fn log_fields() -> anyhow::Result<()> {
+        use crate::{
+            bank::tests::{bank_with, capture_logs},
+            config::{LedgerConfig, LogFields},
+        };
+
+        /// Captures the logs written while processing the test folder.
+        fn capture(log_fields: LogFields) -> anyhow::Result<String> {
+            let test_folder = std::path::Path::new("./test_data/dispute/repeated_unresolved");
+            let config = BankConfig {
+                log_fields,
+                ..Default::default()
+            };
+            let mut bank = bank_with(
+                test_folder.join("input.csv"),
+                LedgerConfig::default(),
+                config,
+            )?;
+
+            let (processed, logs) = capture_logs(|| bank.process())?;
+            processed?;
+            Ok(logs)
+        }
+
+        let full = capture(LogFields::Full)?;
+        assert!(full.contains("Transaction is already disputed"), "{}", full);
+        assert!(full.contains("client_id: 1"), "{}", full);
+        assert!(full.contains("completed_disputes"), "{}", full);
+
+        let minimal = capture(LogFields::Minimal)?;
+        assert!(
+            minimal.contains("Transaction is already disputed"),
+            "{}",
+            minimal
+        );
+        assert!(minimal.contains("client_id: 1"), "{}", minimal);
+        assert!(!minimal.contains("completed_disputes"), "{}", minimal);
+        Ok(())
+    }
+
+    #[test]
+    fn withdrawal_overflow() -> anyhow::Result<()> {
+        use crate::{
+            bank::tests::bank_with,
+            config::{InputFormat, LedgerConfig},
+        };
+        init_tracing().ok();
+
+        // csv amounts this large are not parsed, so the ledger is given as json
+        let test_folder = std::path::Path::new("./test_data/dispute/withdrawal_overflow");
+        let ledger_config = LedgerConfig {
+            format: InputFormat::Json,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.json"),
+            ledger_config,
+            BankConfig::default(),
+        )?;
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(bank.ordered_accounts_balance_buffer()?, expected);
+        assert_eq!(bank.skipped("bank_overflow"), 1);
+        Ok(())
+    }
 }
@@ -0,0 +1,75 @@
+use crate::{
+    account::{AccountInfo, SetAccountInfo},
+    error::HeathResult,
+    transactions::{LoggedAccount, Transaction},
+};
+
+/// A freeze, or unfreeze, is an administrative hold of a client's account, locking it, or lifting
+/// the hold, regardless of any chargeback: unfreezing does not reinstate an account locked by a
+/// chargeback, which requires an unlock. It does not move any funds.
+/// A freeze, or unfreeze, looks like
+/// type client tx amount
+/// freeze 1
+/// unfreeze 1
+#[derive(Debug)]
+pub(super) struct Freeze {
+    account: LoggedAccount,
+    /// Whether the account is frozen, or unfrozen.
+    freeze: bool,
+}
+
+impl Freeze {
+    pub(crate) fn new(account: LoggedAccount, freeze: bool) -> Self {
+        Self { account, freeze }
+    }
+}
+impl Transaction for Freeze {
+    #[tracing::instrument(err)]
+    fn execute(&mut self) -> HeathResult<()> {
+        self.account.set_frozen(self.freeze);
+        tracing::info!(
+            category = if self.freeze { "freeze" } else { "unfreeze" },
+            client = self.account.client_id(),
+            "Account {}",
+            if self.freeze { "frozen" } else { "unfrozen" }
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bank::tests::{test, test_with},
+        config::BankConfig,
+        init_tracing,
+    };
+
+    #[test]
+    fn ok() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/freeze/ok");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn chargeback() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        // a freeze is not a chargeback, and unfreezing does not reinstate a charged back account
+        let test_folder = std::path::Path::new("./test_data/freeze/chargeback");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+
+        let config = BankConfig {
+            stop_at_first_chargeback: true,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+}
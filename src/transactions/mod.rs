@@ -1,24 +1,42 @@
 use crate::{
     account::AccountId,
+    account::AccountInfo,
+    bank::BankAccount,
     client::ClientId,
+    config::{FrozenBehavior, LogFields},
     csv::transaction::{TransactionId, TransactionType},
+    error::{HeathError, HeathResult},
+    store::AccountStore,
     transaction::TransactionLog,
     transactions::{
-        chargeback::ChargeBack, deposit::Deposit, dispute::Dispute, resolve::Resolve,
-        withdrawal::Withdrawal,
+        adjust::Adjust, chargeback::ChargeBack, deposit::Deposit, dispute::Dispute, freeze::Freeze,
+        resolve::Resolve, transfer::Transfer, unlock::Unlock, withdrawal::Withdrawal,
     },
     Bank,
 };
 
+mod adjust;
 mod chargeback;
 mod deposit;
 mod dispute;
+mod freeze;
 mod resolve;
+mod transfer;
+mod unlock;
 mod withdrawal;
 
 /// A transaction, that can be executed
 pub(crate) trait Transaction {
-    fn execute(&mut self) -> anyhow::Result<()>;
+    fn execute(&mut self) -> HeathResult<()>;
+    /// The category of the non-fatal error for which the transaction was ignored, if it was.
+    fn skipped(&self) -> Option<&'static str> {
+        None
+    }
+    /// The funds moved into the bank by the transaction, or out of it when negative, as per its
+    /// own amounts rather than the resulting funds of the accounts.
+    fn flow(&self) -> rust_decimal::Decimal {
+        rust_decimal::Decimal::ZERO
+    }
 }
 
 /// Information about a transaction
@@ -29,30 +47,121 @@ pub(crate) trait TransactionInfo {
     fn amount(&self) -> Option<rust_decimal::Decimal>;
 }
 
+/// The account of a transaction, which is logged with only the configured `LogFields`.
+#[derive(Clone)]
+pub(crate) struct LoggedAccount {
+    account: BankAccount,
+    log_fields: LogFields,
+}
+impl LoggedAccount {
+    fn new(account: BankAccount, log_fields: LogFields) -> Self {
+        Self {
+            account,
+            log_fields,
+        }
+    }
+}
+impl std::ops::Deref for LoggedAccount {
+    type Target = BankAccount;
+    fn deref(&self) -> &Self::Target {
+        &self.account
+    }
+}
+impl std::ops::DerefMut for LoggedAccount {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.account
+    }
+}
+impl std::fmt::Debug for LoggedAccount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.account.try_lock() {
+            Ok(account) => account.fmt_fields(f, self.log_fields),
+            Err(_) => f.write_str("Account { <locked> }"),
+        }
+    }
+}
+
+/// Get the disputed `amount` to release from the `held` funds, clamped to them when it exceeds
+/// them by no more than `epsilon`, eg: by a rounding crumb.
+/// Returns `None` if it exceeds them by more than `epsilon`, ie: the holds are out of sync.
+fn held_within(
+    amount: rust_decimal::Decimal,
+    held: rust_decimal::Decimal,
+    epsilon: rust_decimal::Decimal,
+) -> Option<rust_decimal::Decimal> {
+    if amount <= held {
+        Some(amount)
+    } else if amount - held <= epsilon {
+        Some(held)
+    } else {
+        None
+    }
+}
+
+/// Whether a dispute, resolve or chargeback of the account is blocked as it's frozen, as per the
+/// `FrozenBehavior`.
+fn blocked_when_frozen(account: &BankAccount, frozen: FrozenBehavior) -> bool {
+    if frozen == FrozenBehavior::BlockAll && account.locked() {
+        let error = TransactionError::AccountFrozen {
+            account: account.client_id(),
+        };
+        tracing::debug!(error=%error, "non-fatal error occurred");
+        return true;
+    }
+    false
+}
+
+/// Get the funds of the account computed by a checked operation, eg: `checked_add`, logging the
+/// non-fatal error if they, or the total funds they add up to with the `other` funds of the
+/// account, overflowed the `rust_decimal::Decimal` bounds.
+fn checked_funds(
+    account: &BankAccount,
+    funds: Option<rust_decimal::Decimal>,
+    other: rust_decimal::Decimal,
+) -> Option<rust_decimal::Decimal> {
+    let funds = funds.filter(|funds| funds.checked_add(other).is_some());
+    if funds.is_none() {
+        log_overflow(account);
+    }
+    funds
+}
+
+/// Log the non-fatal error of a transaction whose funds for the account overflowed the
+/// `rust_decimal::Decimal` bounds.
+fn log_overflow(account: &BankAccount) {
+    let error = TransactionError::Overflow {
+        account: account.client_id(),
+    };
+    tracing::debug!(error=%error, "non-fatal error occurred");
+}
+
 /// A bank transaction helper that implements `Transaction`
-pub(crate) struct BankTransaction<'a> {
-    bank: &'a mut Bank,
-    chronological_index: usize,
+/// Its execution is traced within a span carrying the 1-based line of the transaction in the
+/// ledger, header included, so that the non-fatal errors logged may be traced back to its row.
+pub(crate) struct BankTransaction<'a, S> {
+    bank: &'a mut Bank<S>,
     transaction_log: &'a TransactionLog,
+    line: u64,
+    skipped: Option<&'static str>,
+    flow: rust_decimal::Decimal,
 }
 
-impl<'a> BankTransaction<'a> {
+impl<'a, S: AccountStore> BankTransaction<'a, S> {
     /// Return a new `Self`
-    pub(crate) fn new(
-        bank: &'a mut Bank,
-        chronological_index: usize,
-        transaction_log: &'a TransactionLog,
-    ) -> Self {
+    pub(crate) fn new(bank: &'a mut Bank<S>, transaction_log: &'a TransactionLog) -> Self {
         Self {
             bank,
-            chronological_index,
             transaction_log,
+            line: transaction_log.line(),
+            skipped: None,
+            flow: rust_decimal::Decimal::ZERO,
         }
     }
 }
 
-/// A transaction error - was intended to return an error but it turns out we have to ignore
-/// a certain number of "errors" so ended up doing away with it
+/// A non-fatal error of a transaction which cannot be applied, eg: a withdrawal without enough
+/// available funds, a deposit to a frozen account or a malformed ledger row.
+/// It's not returned, as processing carries on: it's only logged and the transaction is skipped.
 #[derive(thiserror::Error, Debug)]
 pub(crate) enum TransactionError {
     #[error("Insufficient Funds (required {required:?}, available {available:?})")]
@@ -62,41 +171,163 @@ pub(crate) enum TransactionError {
     },
     #[error("Account({account:?}) is frozen")]
     AccountFrozen { account: AccountId },
+    #[error("Account({account:?}) funds overflow")]
+    Overflow { account: AccountId },
+    #[error("Malformed transaction: {0}")]
+    Malformed(HeathError),
 }
 
-impl<'a> Transaction for BankTransaction<'a> {
-    fn execute(&mut self) -> anyhow::Result<()> {
-        let account = self.bank.account(self.transaction_log.client_id());
-        match self.transaction_log {
-            TransactionLog::Deposit { amount, .. } => Deposit::new(account, *amount).execute(),
+impl<'a, S: AccountStore> BankTransaction<'a, S> {
+    /// Get the transaction referenced by this dispute, resolve or chargeback.
+    fn disputed_transaction(&self) -> HeathResult<Option<TransactionLog>> {
+        Ok(self.bank.disputed_transaction(
+            self.transaction_log.client_id(),
+            self.transaction_log.transaction_id(),
+        ))
+    }
+    /// Get the client owning the transaction referenced by this dispute, resolve or chargeback,
+    /// if it was not found as it belongs to another client, eg: the partner confused the clients.
+    fn other_client(&self, disputed_tx: &Option<TransactionLog>) -> Option<ClientId> {
+        if disputed_tx.is_some() || self.bank.config().cross_client_disputes {
+            return None;
+        }
+        self.bank
+            .transaction_owner(self.transaction_log.transaction_id())
+            .filter(|owner| *owner != self.transaction_log.client_id())
+    }
+    /// Skip this dispute, resolve or chargeback, with a warning, if it references a transaction of
+    /// another client, returning whether it was skipped.
+    fn skip_other_client(&mut self, disputed_tx: &Option<TransactionLog>) -> bool {
+        let owner = match self.other_client(disputed_tx) {
+            Some(owner) => owner,
+            None => return false,
+        };
+        tracing::warn!(transaction=?self.transaction_log, owner, "Transaction belongs to another client");
+        let category = match self.transaction_log {
+            TransactionLog::Dispute { .. } => "dispute_wrong_client",
+            TransactionLog::Resolve { .. } => "resolve_wrong_client",
+            _ => "chargeback_wrong_client",
+        };
+        self.skipped = Some(category);
+        self.bank.count_skipped(self.transaction_log, category);
+        true
+    }
+    /// Get the account holding the funds of the `disputed_tx`.
+    fn dispute_account(
+        &mut self,
+        disputed_tx: &Option<TransactionLog>,
+    ) -> HeathResult<LoggedAccount> {
+        let account = self
+            .bank
+            .dispute_account(self.transaction_log.client_id(), disputed_tx)?;
+        Ok(LoggedAccount::new(account, self.bank.config().log_fields))
+    }
+}
+
+impl<'a, S: AccountStore> Transaction for BankTransaction<'a, S> {
+    fn execute(&mut self) -> HeathResult<()> {
+        let _span = tracing::debug_span!("transaction", line = self.line).entered();
+        let client_id = self.transaction_log.client_id();
+        if let TransactionLog::Unlock { .. } = self.transaction_log {
+            // an unlock must not create the account it reinstates
+            if !self.bank.has_account(client_id)? {
+                tracing::warn!(client = client_id, "Unlock of an unknown client");
+                self.skipped = Some("unlock_unknown_client");
+                self.bank
+                    .count_skipped(self.transaction_log, "unlock_unknown_client");
+                return Ok(());
+            }
+        }
+        let account = self.bank.account(client_id)?;
+        let account = LoggedAccount::new(account, self.bank.config().log_fields);
+        let mut transaction: Box<dyn Transaction + '_> = match self.transaction_log {
+            TransactionLog::Deposit { amount, .. } => {
+                let frozen = self.bank.config().frozen_deposit_policy;
+                Box::new(Deposit::new(account, *amount, frozen))
+            }
             TransactionLog::Withdrawal { amount, .. } => {
-                Withdrawal::new(account, *amount).execute()
+                let partial = self.bank.config().partial_withdrawals;
+                Box::new(Withdrawal::new(account, *amount, partial))
             }
 
-            TransactionLog::Dispute { .. } => {
-                let dispute = self.bank.transaction(
-                    self.chronological_index,
-                    self.transaction_log.client_id(),
-                    self.transaction_log.transaction_id(),
-                )?;
-                Dispute::new(account, dispute).execute()
+            TransactionLog::Dispute {
+                percent, amount, ..
+            } => {
+                let dispute = self.disputed_transaction()?;
+                if self.skip_other_client(&dispute) {
+                    return Ok(());
+                }
+                let account = self.dispute_account(&dispute)?;
+                let policy = self.bank.config().repeated_dispute_policy;
+                let insufficient = self.bank.config().dispute_policy;
+                let frozen = self.bank.config().frozen_behavior;
+                Box::new(Dispute::new(
+                    account,
+                    dispute,
+                    *percent,
+                    *amount,
+                    policy,
+                    insufficient,
+                    frozen,
+                ))
             }
             TransactionLog::Resolve { .. } => {
-                let dispute = self.bank.transaction(
-                    self.chronological_index,
-                    self.transaction_log.client_id(),
-                    self.transaction_log.transaction_id(),
-                )?;
-                Resolve::new(account, dispute).execute()
+                let dispute = self.disputed_transaction()?;
+                if self.skip_other_client(&dispute) {
+                    return Ok(());
+                }
+                let account = self.dispute_account(&dispute)?;
+                let held_epsilon = self.bank.config().held_epsilon;
+                let frozen = self.bank.config().frozen_behavior;
+                let final_resolve = self.bank.config().no_redispute;
+                Box::new(Resolve::new(
+                    account,
+                    dispute,
+                    held_epsilon,
+                    frozen,
+                    final_resolve,
+                ))
             }
             TransactionLog::Chargeback { .. } => {
-                let dispute = self.bank.transaction(
-                    self.chronological_index,
-                    self.transaction_log.client_id(),
-                    self.transaction_log.transaction_id(),
-                )?;
-                ChargeBack::new(account, dispute).execute()
+                let dispute = self.disputed_transaction()?;
+                if self.skip_other_client(&dispute) {
+                    return Ok(());
+                }
+                let account = self.dispute_account(&dispute)?;
+                let implicit_dispute = self.bank.config().implicit_dispute_on_chargeback;
+                let held_epsilon = self.bank.config().held_epsilon;
+                let frozen = self.bank.config().frozen_behavior;
+                Box::new(ChargeBack::new(
+                    account,
+                    dispute,
+                    implicit_dispute,
+                    held_epsilon,
+                    frozen,
+                ))
+            }
+            TransactionLog::Adjust { amount, .. } => Box::new(Adjust::new(account, *amount)),
+            TransactionLog::Unlock { .. } => Box::new(Unlock::new(account)),
+            TransactionLog::Freeze { .. } => Box::new(Freeze::new(account, true)),
+            TransactionLog::Unfreeze { .. } => Box::new(Freeze::new(account, false)),
+            TransactionLog::Transfer { dest, amount, .. } => {
+                let bank = &mut *self.bank;
+                Box::new(Transfer::new(account, move || bank.account(*dest), *amount))
             }
+        };
+        transaction.execute()?;
+        self.skipped = transaction.skipped();
+        self.flow = transaction.flow();
+        // which may borrow the bank, eg: to open the destination of a transfer
+        drop(transaction);
+        if let Some(category) = self.skipped {
+            self.bank.count_skipped(self.transaction_log, category);
         }
+        Ok(())
+    }
+    fn skipped(&self) -> Option<&'static str> {
+        self.skipped
+    }
+    fn flow(&self) -> rust_decimal::Decimal {
+        self.flow
     }
 }
@@ -0,0 +1,185 @@
+use crate::{
+    account::{AccountId, AccountInfo, SetAccountInfo},
+    bank::BankAccount,
+    error::HeathResult,
+    transactions::{checked_funds, LoggedAccount, Transaction, TransactionError},
+};
+
+/// A transfer moves funds from the source client's account into the destination client's
+/// account, meaning it decreases the available and total funds of the source and increases those
+/// of the destination by the same amount.
+/// A transfer looks like
+/// type client tx amount dest
+/// transfer 1 3 1.0 2
+/// # Non-Fatal Error
+/// As with a withdrawal, if the source client does not have sufficient available funds, plus its
+/// overdraft limit, the transfer should fail and neither account should change. The transfer also
+/// fails if either account is frozen.
+/// The destination account is only opened once the source has the funds, so that a failed
+/// transfer does not create it.
+pub(super) struct Transfer<'a> {
+    source: LoggedAccount,
+    dest: Option<Box<dyn FnOnce() -> HeathResult<BankAccount> + 'a>>,
+    amount: rust_decimal::Decimal,
+    skipped: Option<&'static str>,
+}
+impl<'a> Transfer<'a> {
+    pub(crate) fn new(
+        source: LoggedAccount,
+        dest: impl FnOnce() -> HeathResult<BankAccount> + 'a,
+        amount: rust_decimal::Decimal,
+    ) -> Self {
+        Self {
+            source,
+            dest: Some(Box::new(dest)),
+            amount,
+            skipped: None,
+        }
+    }
+    /// Skip the transfer as either account is frozen.
+    fn frozen(&mut self, account: AccountId) {
+        let error = TransactionError::AccountFrozen { account };
+        tracing::debug!(error=%error, "non-fatal error occurred");
+        self.skipped = Some("transfer_account_frozen");
+    }
+}
+impl std::fmt::Debug for Transfer<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transfer")
+            .field("source", &self.source)
+            .field("amount", &self.amount)
+            .field("skipped", &self.skipped)
+            .finish()
+    }
+}
+impl Transaction for Transfer<'_> {
+    #[tracing::instrument(err)]
+    fn execute(&mut self) -> HeathResult<()> {
+        if self.source.locked() {
+            self.frozen(self.source.client_id());
+            return Ok(());
+        }
+        let available = self.source.available_funds();
+        let withdrawable = available.saturating_add(self.source.overdraft_limit());
+        if withdrawable < self.amount {
+            let error = TransactionError::InsufficientFunds {
+                required: self.amount,
+                available,
+            };
+            tracing::debug!(error=%error, "non-fatal error occurred");
+            self.skipped = Some("transfer_insufficient_funds");
+            return Ok(());
+        }
+        let source_available = available.checked_sub(self.amount);
+        let source_available =
+            match checked_funds(&self.source, source_available, self.source.held_funds()) {
+                Some(source_available) => source_available,
+                None => {
+                    self.skipped = Some("transfer_overflow");
+                    return Ok(());
+                }
+            };
+        let mut dest = match self.dest.take() {
+            Some(dest) => dest()?,
+            None => return Ok(()),
+        };
+        // a frozen destination already existed, as new accounts are not frozen
+        if dest.locked() {
+            self.frozen(dest.client_id());
+            return Ok(());
+        }
+        self.source.set_available_funds(source_available);
+        // read once debited, as the destination may be the source itself
+        let dest_available = dest.available_funds().checked_add(self.amount);
+        match checked_funds(&dest, dest_available, dest.held_funds()) {
+            Some(dest_available) => dest.set_available_funds(dest_available),
+            None => {
+                self.source.set_available_funds(available);
+                self.skipped = Some("transfer_overflow");
+            }
+        }
+        Ok(())
+    }
+    fn skipped(&self) -> Option<&'static str> {
+        self.skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bank::tests::test, init_tracing};
+
+    #[test]
+    fn ok() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/transfer/ok");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn no_funds() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/transfer/no_funds");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_dest() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        // the failed transfer does not open an account for the destination
+        let test_folder = std::path::Path::new("./test_data/transfer/unknown_dest");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn overdraft() -> anyhow::Result<()> {
+        use crate::{bank::tests::test_with, config::BankConfig};
+        use rust_decimal_macros::dec;
+        init_tracing().ok();
+
+        // as with withdrawals, the second transfer would exceed the overdraft limit
+        let test_folder = std::path::Path::new("./test_data/transfer/overdraft");
+        let config = BankConfig {
+            overdraft_limits: [(1, dec!(5))].iter().copied().collect(),
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn overflow() -> anyhow::Result<()> {
+        use crate::{
+            bank::tests::bank_with,
+            config::{BankConfig, InputFormat, LedgerConfig},
+        };
+        init_tracing().ok();
+
+        // csv amounts this large are not parsed, so the ledger is given as json
+        let test_folder = std::path::Path::new("./test_data/transfer/overflow");
+        let ledger_config = LedgerConfig {
+            format: InputFormat::Json,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.json"),
+            ledger_config,
+            BankConfig::default(),
+        )?;
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(bank.ordered_accounts_balance_buffer()?, expected);
+        // the second deposit would overflow the funds deposited into the bank
+        assert_eq!(bank.skipped("bank_overflow"), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{
+    account::{AccountInfo, SetAccountInfo},
+    error::HeathResult,
+    transactions::{LoggedAccount, Transaction},
+};
+
+/// An unlock is an administrative reinstatement of a client's account which was locked by a
+/// chargeback, allowing it to transact again, unless it's also frozen. It does not move any funds.
+/// An unlock looks like
+/// type client tx amount
+/// unlock 1
+/// # Non-Fatal Error
+/// If the account is not locked by a chargeback, or does not exist, the unlock is ignored, with a
+/// warning.
+#[derive(Debug)]
+pub(super) struct Unlock {
+    account: LoggedAccount,
+    skipped: Option<&'static str>,
+}
+
+impl Unlock {
+    pub(crate) fn new(account: LoggedAccount) -> Self {
+        Self {
+            account,
+            skipped: None,
+        }
+    }
+}
+impl Transaction for Unlock {
+    #[tracing::instrument(err)]
+    fn execute(&mut self) -> HeathResult<()> {
+        if self.account.charged_back() {
+            self.account.set_locked(false);
+            tracing::info!(
+                category = "unlock",
+                client = self.account.client_id(),
+                "Account unlocked"
+            );
+        } else {
+            tracing::warn!(
+                client = self.account.client_id(),
+                "Unlock of an account which is not locked"
+            );
+            self.skipped = Some("unlock_not_locked");
+        }
+        Ok(())
+    }
+    fn skipped(&self) -> Option<&'static str> {
+        self.skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bank::tests::test, init_tracing};
+
+    #[test]
+    fn ok() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/unlock/ok");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        // the unknown client's account is not created
+        let test_folder = std::path::Path::new("./test_data/unlock/unknown");
+        let mut bank = crate::bank::tests::bank(test_folder.join("input.csv"))?;
+        let expected = std::fs::read_to_string(test_folder.join("output.csv"))?;
+        assert_eq!(bank.ordered_accounts_balance_buffer()?, expected);
+        assert_eq!(bank.skipped("unlock_unknown_client"), 1);
+        Ok(())
+    }
+}
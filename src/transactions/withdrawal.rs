@@ -1,7 +1,7 @@
 use crate::{
     account::{AccountInfo, SetAccountInfo},
-    bank::BankAccount,
-    transactions::{Transaction, TransactionError},
+    error::HeathResult,
+    transactions::{checked_funds, LoggedAccount, Transaction, TransactionError},
 };
 
 /// A withdraw is a debit to the client's asset account, meaning it should decrease the available
@@ -10,46 +10,91 @@ use crate::{
 /// type client tx amount
 /// withdrawal 2 2 1.0
 /// # Non-Fatal Error
-/// If a client does not have sufficient available funds the withdrawal should fail and the
-/// total amount of funds should not change
+/// If a client does not have sufficient available funds, plus its overdraft limit, the withdrawal
+/// should fail and the total amount of funds should not change, unless partial withdrawals are
+/// enabled in which case the available funds are drained down to the overdraft limit.
 #[derive(Debug)]
 pub(super) struct Withdrawal {
-    account: BankAccount,
+    account: LoggedAccount,
     amount: rust_decimal::Decimal,
+    partial: bool,
+    /// The funds withdrawn, which may be less than the amount if partially withdrawn.
+    withdrawn: rust_decimal::Decimal,
+    skipped: Option<&'static str>,
 }
 impl Withdrawal {
-    pub(crate) fn new(account: BankAccount, amount: rust_decimal::Decimal) -> Self {
-        Self { account, amount }
+    pub(crate) fn new(
+        account: LoggedAccount,
+        amount: rust_decimal::Decimal,
+        partial: bool,
+    ) -> Self {
+        Self {
+            account,
+            amount,
+            partial,
+            withdrawn: rust_decimal::Decimal::ZERO,
+            skipped: None,
+        }
     }
 }
 impl Transaction for Withdrawal {
     #[tracing::instrument(err)]
-    fn execute(&mut self) -> anyhow::Result<()> {
+    fn execute(&mut self) -> HeathResult<()> {
         if self.account.locked() {
             let error = TransactionError::AccountFrozen {
                 account: self.account.client_id(),
             };
             tracing::debug!(error=%error, "non-fatal error occurred");
+            self.skipped = Some("withdrawal_account_frozen");
             return Ok(());
         }
         let available = self.account.available_funds();
-        if available >= self.amount {
-            let new_available = available - self.amount;
-            self.account.set_available_funds(new_available);
+        let overdraft_limit = self.account.overdraft_limit();
+        let withdrawable = available.saturating_add(overdraft_limit);
+        if withdrawable >= self.amount {
+            match checked_funds(
+                &self.account,
+                available.checked_sub(self.amount),
+                self.account.held_funds(),
+            ) {
+                Some(new_available) => {
+                    self.account.set_available_funds(new_available);
+                    self.withdrawn = self.amount;
+                }
+                None => self.skipped = Some("withdrawal_overflow"),
+            }
+        } else if self.partial && withdrawable > rust_decimal::Decimal::ZERO {
+            let shortfall = self.amount - withdrawable;
+            self.account.set_available_funds(-overdraft_limit);
+            self.withdrawn = withdrawable;
+            tracing::debug!(account=?self.account, shortfall=%shortfall, "Partial withdrawal");
         } else {
             let error = TransactionError::InsufficientFunds {
                 required: self.amount,
                 available,
             };
             tracing::debug!(error=%error, "non-fatal error occurred");
+            self.skipped = Some("withdrawal_insufficient_funds");
         }
         Ok(())
     }
+    fn skipped(&self) -> Option<&'static str> {
+        self.skipped
+    }
+    fn flow(&self) -> rust_decimal::Decimal {
+        -self.withdrawn
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{bank::tests::test, init_tracing};
+    use crate::{
+        bank::tests::{bank_with, test, test_with},
+        config::{BankConfig, LedgerConfig},
+        csv::error_log::ErrorLog,
+        init_tracing,
+    };
+    use rust_decimal_macros::dec;
 
     #[test]
     fn no_funds() -> anyhow::Result<()> {
@@ -61,6 +106,81 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn no_funds_error_log() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/withdrawal/no_funds");
+        let config = BankConfig {
+            error_log: true,
+            ..Default::default()
+        };
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            config,
+        )?;
+        bank.process()?;
+        assert_eq!(
+            bank.error_log(),
+            &[ErrorLog {
+                line: 6,
+                client: Some(2),
+                tx: Some(5),
+                reason: "withdrawal_insufficient_funds",
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_funds_line() -> anyhow::Result<()> {
+        use crate::bank::tests::capture_logs;
+
+        let test_folder = std::path::Path::new("./test_data/withdrawal/no_funds");
+        let mut bank = bank_with(
+            test_folder.join("input.csv"),
+            LedgerConfig::default(),
+            BankConfig::default(),
+        )?;
+
+        let (processed, logs) = capture_logs(|| bank.process())?;
+        processed?;
+        let warning = logs
+            .lines()
+            .find(|line| line.contains("Insufficient Funds"))
+            .unwrap_or_default();
+        assert!(warning.contains("transaction{line=6}"), "{}", logs);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_rejected() -> anyhow::Result<()> {
+        use crate::bank::tests::bank;
+        init_tracing().ok();
+
+        // a rejected withdrawal is not indexed, so charging it back cannot credit its amount
+        let test_folder = std::path::Path::new("./test_data/withdrawal/dispute_rejected");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+
+        let mut bank = bank(test_folder.join("input.csv"))?;
+        bank.process()?;
+        assert_eq!(bank.skipped("dispute_unknown_transaction"), 1);
+        assert_eq!(bank.skipped("chargeback_unknown_transaction"), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn negative() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/withdrawal/negative");
+        let (expected, actual) = test(test_folder)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
     #[test]
     fn ok() -> anyhow::Result<()> {
         init_tracing().ok();
@@ -70,4 +190,72 @@ mod tests {
         assert_eq!(expected, actual);
         Ok(())
     }
+
+    #[test]
+    fn partial() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/withdrawal/partial");
+        let config = BankConfig {
+            partial_withdrawals: true,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+
+        // by default the withdrawal is rejected
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("1,2,0,2,false"), "{}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn overdraft_ok() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/withdrawal/overdraft_ok");
+        let config = BankConfig {
+            overdraft_limits: [(1, dec!(5))].iter().copied().collect(),
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+
+        // by default there's no overdraft
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("1,1,0,1,false"), "{}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn overdraft_exceeded() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/withdrawal/overdraft_exceeded");
+        let config = BankConfig {
+            overdraft_limits: [(1, dec!(5))].iter().copied().collect(),
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn unseen_client() -> anyhow::Result<()> {
+        init_tracing().ok();
+
+        let test_folder = std::path::Path::new("./test_data/withdrawal/unseen_client");
+        let config = BankConfig {
+            no_phantom_on_withdrawal: true,
+            ..Default::default()
+        };
+        let (expected, actual) = test_with(test_folder, Default::default(), config)?;
+        assert_eq!(expected, actual);
+
+        // by default the withdrawal opens an empty account
+        let (_, actual) = test(test_folder)?;
+        assert!(actual.contains("2,0,0,0,false"), "{}", actual);
+        Ok(())
+    }
 }